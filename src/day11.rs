@@ -1,11 +1,18 @@
 use nom::character::complete::{newline, one_of};
-use nom::combinator::{all_consuming, map, opt};
+use nom::combinator::{all_consuming, map, opt, verify};
 use nom::multi::{many1, separated_list1};
 use nom::sequence::terminated;
 use nom::IResult;
+use std::collections::HashMap;
 use std::convert::{From, TryFrom, TryInto};
 use std::env;
-use std::fs;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
 
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 enum SeatState {
@@ -27,6 +34,16 @@ impl TryFrom<char> for SeatState {
     }
 }
 
+impl From<SeatState> for char {
+    fn from(state: SeatState) -> char {
+        match state {
+            SeatState::Empty => 'L',
+            SeatState::Occupied => '#',
+            SeatState::Floor => '.',
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug)]
 struct Grid(Vec<Vec<SeatState>>);
 
@@ -36,8 +53,57 @@ impl From<Vec<Vec<SeatState>>> for Grid {
     }
 }
 
-impl Grid {
-    fn seats_to_check_part1(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+// The same format the grid is parsed from, with a newline after every row
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in &self.0 {
+            let row: String = row.iter().map(|state| char::from(*state)).collect();
+            writeln!(f, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Grid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Grid, String> {
+        parse_grid(s).map(|(_, grid)| grid).map_err(|_| {
+            // Point out the first row which is a different length, if that's the problem
+            let mut lines = s.lines().enumerate();
+            let width = lines.next().map_or(0, |(_, line)| line.len());
+            match lines.find(|(_, line)| line.len() != width && !line.is_empty()) {
+                Some((y, line)) if line.chars().all(|c| ".L#".contains(c)) => format!(
+                    "Row {} has {} seats, but the first row has {}",
+                    y + 1,
+                    line.len(),
+                    width
+                ),
+                _ => "Grids can only contain '.', 'L' and '#', in rows of equal length".to_string(),
+            }
+        })
+    }
+}
+
+// The seats a seat looks at when deciding whether to change
+trait Neighbourhood {
+    fn neighbours(&self, grid: &Grid, x: usize, y: usize) -> Vec<(usize, usize)>;
+}
+
+// The (up to) 8 seats surrounding a seat, as in part 1
+struct Adjacent;
+
+// The first seat visible in each of the 8 directions, looking past floor, as in part 2
+struct LineOfSight;
+
+// The (up to) 4 seats directly above, below, left and right of a seat
+struct VonNeumann;
+
+// Every seat within the given number of rows and columns of a seat
+struct Radius(usize);
+
+impl Neighbourhood for Adjacent {
+    fn neighbours(&self, grid: &Grid, x: usize, y: usize) -> Vec<(usize, usize)> {
         // Build a list of seats to check for the given x, y.
         // In the simple case this will be 8 coords in the range x/y +- 1 excluding
         // (x, y) itself, however we also need to handle coords in the first/last col/
@@ -50,7 +116,7 @@ impl Grid {
                 to_check.push((x - 1, y - 1))
             };
             to_check.push((x, y - 1));
-            if x < self.0[y - 1].len() - 1 {
+            if x < grid.0[y - 1].len() - 1 {
                 to_check.push((x + 1, y - 1))
             };
         }
@@ -58,23 +124,25 @@ impl Grid {
         if x > 0 {
             to_check.push((x - 1, y))
         };
-        if x < self.0[y].len() - 1 {
+        if x < grid.0[y].len() - 1 {
             to_check.push((x + 1, y))
         };
         // Row below
-        if y < self.0.len() - 1 {
+        if y < grid.0.len() - 1 {
             if x > 0 {
                 to_check.push((x - 1, y + 1))
             };
             to_check.push((x, y + 1));
-            if x < self.0[y + 1].len() - 1 {
+            if x < grid.0[y + 1].len() - 1 {
                 to_check.push((x + 1, y + 1))
             };
         }
         to_check
     }
+}
 
-    fn seats_to_check_part2(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
+impl Neighbourhood for LineOfSight {
+    fn neighbours(&self, grid: &Grid, x: usize, y: usize) -> Vec<(usize, usize)> {
         // Check each of the 8 directions from seat (x, y) for the next seat.
         [
             (-1, -1),
@@ -96,7 +164,7 @@ impl Grid {
                     // We've gone past the top/left, so there's no seat in this dir.
                     break None;
                 }
-                match self.get(seat.0 as usize, seat.1 as usize) {
+                match grid.get(seat.0 as usize, seat.1 as usize) {
                     Some(SeatState::Occupied) | Some(SeatState::Empty) => {
                         break Some((seat.0 as usize, seat.1 as usize))
                     }
@@ -108,18 +176,91 @@ impl Grid {
         .filter_map(|s| s)
         .collect()
     }
+}
 
-    fn count_occupied_neighbors(&self, x: usize, y: usize, part2: bool) -> u8 {
-        let to_check = if part2 {
-            self.seats_to_check_part2(x, y)
-        } else {
-            self.seats_to_check_part1(x, y)
-        };
+impl Neighbourhood for VonNeumann {
+    fn neighbours(&self, grid: &Grid, x: usize, y: usize) -> Vec<(usize, usize)> {
+        let mut to_check = vec![(x + 1, y), (x, y + 1)];
+        if y > 0 {
+            to_check.push((x, y - 1));
+        }
+        if x > 0 {
+            to_check.push((x - 1, y));
+        }
         to_check
+            .into_iter()
+            .filter(|(x, y)| grid.get(*x, *y).is_some())
+            .collect()
+    }
+}
+
+impl Neighbourhood for Radius {
+    fn neighbours(&self, grid: &Grid, x: usize, y: usize) -> Vec<(usize, usize)> {
+        // Only look at the part of the square which is inside the grid
+        let r = self.0;
+        let (last_x, last_y) = (grid.0[y].len() - 1, grid.0.len() - 1);
+        let xs = x.saturating_sub(r)..=x.saturating_add(r).min(last_x);
+        (y.saturating_sub(r)..=y.saturating_add(r).min(last_y))
+            .flat_map(|ny| xs.clone().map(move |nx| (nx, ny)))
+            .filter(|seat| *seat != (x, y))
+            .collect()
+    }
+}
+
+fn parse_neighbourhood(name: &str) -> Result<Box<dyn Neighbourhood>, String> {
+    match name {
+        "adjacent" => Ok(Box::new(Adjacent)),
+        "line-of-sight" => Ok(Box::new(LineOfSight)),
+        "von-neumann" => Ok(Box::new(VonNeumann)),
+        _ => match name.strip_prefix("radius:").map(str::parse) {
+            Some(Ok(r)) => Ok(Box::new(Radius(r))),
+            _ => Err(format!("Unknown neighbourhood '{}'", name)),
+        },
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+struct SeatRules {
+    // An occupied seat empties if at least this many of its neighbours are occupied
+    tolerance: usize,
+    // An empty seat fills if at most this many of its neighbours are occupied
+    birth_condition: usize,
+}
+
+impl SeatRules {
+    const PART1: SeatRules = SeatRules {
+        tolerance: 4,
+        birth_condition: 0,
+    };
+    const PART2: SeatRules = SeatRules {
+        tolerance: 5,
+        birth_condition: 0,
+    };
+
+    fn next_state(&self, state: SeatState, occupied_neighbours: usize) -> SeatState {
+        match state {
+            SeatState::Floor => SeatState::Floor, // Floor never changes
+            SeatState::Empty if occupied_neighbours <= self.birth_condition => SeatState::Occupied,
+            SeatState::Occupied if occupied_neighbours >= self.tolerance => SeatState::Empty,
+            state => state,
+        }
+    }
+}
+
+impl Grid {
+    #[cfg(test)]
+    fn count_occupied_neighbors(
+        &self,
+        x: usize,
+        y: usize,
+        neighbourhood: &dyn Neighbourhood,
+    ) -> usize {
+        neighbourhood
+            .neighbours(self, x, y)
             .iter()
             .map(|(x, y)| self.get(*x, *y))
             .filter(|state| *state == Some(SeatState::Occupied))
-            .count() as u8
+            .count()
     }
     fn get(&self, x: usize, y: usize) -> Option<SeatState> {
         if let Some(row) = self.0.get(y) {
@@ -130,7 +271,9 @@ impl Grid {
         None
     }
 
-    fn generate(&self, part2: bool) -> Grid {
+    // The straightforward generation step, which the simulation is checked against
+    #[cfg(test)]
+    fn generate(&self, neighbourhood: &dyn Neighbourhood, rules: SeatRules) -> Grid {
         self.0
             .iter()
             .enumerate()
@@ -139,24 +282,8 @@ impl Grid {
                     .iter()
                     .enumerate()
                     .map(|(x, c)| {
-                        let occupied_count = self.count_occupied_neighbors(x, y, part2);
-                        return match c {
-                            SeatState::Floor => SeatState::Floor, // Floor never changes
-                            SeatState::Empty => {
-                                if occupied_count == 0 {
-                                    SeatState::Occupied
-                                } else {
-                                    SeatState::Empty
-                                }
-                            }
-                            SeatState::Occupied => {
-                                if occupied_count >= if part2 {5} else {4} {
-                                    SeatState::Empty
-                                } else {
-                                    SeatState::Occupied
-                                }
-                            }
-                        };
+                        let occupied_count = self.count_occupied_neighbors(x, y, neighbourhood);
+                        rules.next_state(*c, occupied_count)
                     })
                     .collect();
             })
@@ -165,12 +292,280 @@ impl Grid {
     }
 }
 
+// A grid flattened for running many generations quickly. Each seat's neighbours are found
+// once up front, the states are kept in two buffers which are swapped each generation, and
+// only the seats which changed last generation, or which watch one that did, are checked.
+struct Simulation {
+    width: usize,
+    rules: SeatRules,
+    cells: Vec<SeatState>,
+    // The previous generation, which becomes the next one
+    next: Vec<SeatState>,
+    // The indices of each seat's neighbours. Floor has none, as it never changes.
+    neighbours: Vec<Vec<usize>>,
+    // The indices of the seats which each seat is a neighbour of
+    watchers: Vec<Vec<usize>>,
+    // The seats to check next generation
+    to_check: Vec<usize>,
+    // A hash of which seats are occupied, kept up to date as seats change
+    hash: u64,
+}
+
+// The value a seat contributes to the hash of a generation while it's occupied. This is the
+// finaliser from splitmix64, which scatters consecutive indices well.
+fn seat_hash(i: usize) -> u64 {
+    let mut z = (i as u64)
+        .wrapping_add(1)
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Simulation {
+    // Short rows are padded out with floor
+    fn new(grid: &Grid, neighbourhood: &dyn Neighbourhood, rules: SeatRules) -> Self {
+        let width = grid.0.iter().map(Vec::len).max().unwrap_or(0);
+        let mut cells = vec![SeatState::Floor; width * grid.0.len()];
+        let mut neighbours = vec![vec![]; cells.len()];
+        let mut watchers = vec![vec![]; cells.len()];
+        for (y, row) in grid.0.iter().enumerate() {
+            for (x, state) in row.iter().enumerate() {
+                let i = y * width + x;
+                cells[i] = *state;
+                if *state == SeatState::Floor {
+                    continue;
+                }
+                for (nx, ny) in neighbourhood.neighbours(grid, x, y) {
+                    if grid.get(nx, ny) != Some(SeatState::Floor) {
+                        let n = ny * width + nx;
+                        neighbours[i].push(n);
+                        watchers[n].push(i);
+                    }
+                }
+            }
+        }
+        let to_check = (0..cells.len())
+            .filter(|i| cells[*i] != SeatState::Floor)
+            .collect();
+        let hash = (0..cells.len())
+            .filter(|i| cells[*i] == SeatState::Occupied)
+            .fold(0, |hash, i| hash ^ seat_hash(i));
+        Self {
+            width,
+            rules,
+            next: cells.clone(),
+            cells,
+            neighbours,
+            watchers,
+            to_check,
+            hash,
+        }
+    }
+
+    // Run one generation, returning whether any seat changed
+    fn step(&mut self) -> bool {
+        // The buffers only differ at seats which changed last generation, and those are all
+        // checked, so every seat in the next buffer is up to date afterwards
+        let mut changed = vec![];
+        for &i in &self.to_check {
+            let occupied = self.neighbours[i]
+                .iter()
+                .filter(|n| self.cells[**n] == SeatState::Occupied)
+                .count();
+            self.next[i] = self.rules.next_state(self.cells[i], occupied);
+            if self.next[i] != self.cells[i] {
+                // Seats only change between empty and occupied
+                self.hash ^= seat_hash(i);
+                changed.push(i);
+            }
+        }
+        std::mem::swap(&mut self.cells, &mut self.next);
+
+        let mut queued = vec![false; self.cells.len()];
+        self.to_check.clear();
+        for i in &changed {
+            for &j in std::iter::once(i).chain(&self.watchers[*i]) {
+                if !queued[j] {
+                    queued[j] = true;
+                    self.to_check.push(j);
+                }
+            }
+        }
+        !changed.is_empty()
+    }
+
+    fn occupied(&self) -> usize {
+        self.cells
+            .iter()
+            .filter(|s| **s == SeatState::Occupied)
+            .count()
+    }
+
+    // Which seats are occupied, packed 64 to a word. Floor never changes, so this is all
+    // that differs between generations, in an eighth of the space of the cells.
+    fn occupancy(&self) -> Vec<u64> {
+        let mut bits = vec![0; self.cells.len().div_ceil(64)];
+        for (i, state) in self.cells.iter().enumerate() {
+            if *state == SeatState::Occupied {
+                bits[i / 64] |= 1 << (i % 64);
+            }
+        }
+        bits
+    }
+
+    // The current generation as text, with ANSI colours if asked for
+    fn render(&self, colour: bool) -> String {
+        let mut text = String::new();
+        for row in self.cells.chunks(self.width) {
+            for state in row {
+                let c = char::from(*state);
+                if colour {
+                    let code = match state {
+                        SeatState::Occupied => "31",
+                        SeatState::Empty => "32",
+                        SeatState::Floor => "90",
+                    };
+                    text.push_str(&format!("\x1b[{}m{}\x1b[0m", code, c));
+                } else {
+                    text.push(c);
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // Write the current generation as a binary PPM image, with each seat drawn as a square
+    // scale pixels across
+    fn write_ppm(&self, mut out: impl Write, scale: usize) -> io::Result<()> {
+        let height = self.cells.len() / self.width;
+        write!(out, "P6\n{} {}\n255\n", self.width * scale, height * scale)?;
+        for row in self.cells.chunks(self.width) {
+            let pixels = row
+                .iter()
+                .flat_map(|state| {
+                    let rgb: [u8; 3] = match state {
+                        SeatState::Occupied => [220, 50, 47],
+                        SeatState::Empty => [133, 153, 0],
+                        SeatState::Floor => [40, 40, 40],
+                    };
+                    rgb.repeat(scale)
+                })
+                .collect::<Vec<u8>>();
+            for _ in 0..scale {
+                out.write_all(&pixels)?;
+            }
+        }
+        out.flush()
+    }
+}
+
+// Where a simulation starts repeating itself. A layout which settles down has a period of 1.
+#[derive(PartialEq, Eq, Debug)]
+struct Cycle {
+    start: usize,
+    period: usize,
+}
+
+// The occupancy of every generation seen so far, grouped by hash. Different generations
+// can share a hash, so the hash only narrows down which ones to compare.
+#[derive(Default)]
+struct History(HashMap<u64, Vec<(usize, Vec<u64>)>>);
+
+impl History {
+    // Record a generation, returning the generation it repeats if it's been seen before
+    fn insert(&mut self, hash: u64, occupancy: Vec<u64>, generation: usize) -> Option<usize> {
+        let same_hash = self.0.entry(hash).or_default();
+        match same_hash.iter().find(|(_, seen)| *seen == occupancy) {
+            Some((start, _)) => Some(*start),
+            None => {
+                same_hash.push((generation, occupancy));
+                None
+            }
+        }
+    }
+}
+
+// Run generations until one repeats an earlier one, passing each new generation to show.
+// Gives up after max_generations, if given.
+fn run(
+    simulation: &mut Simulation,
+    max_generations: Option<usize>,
+    mut show: impl FnMut(&Simulation, usize),
+) -> Result<Cycle, String> {
+    let mut history = History::default();
+    history.insert(simulation.hash, simulation.occupancy(), 0);
+    show(simulation, 0);
+    let mut generation = 1;
+    loop {
+        if let Some(max) = max_generations {
+            if generation > max {
+                return Err(format!("No repeated generation within {} generations", max));
+            }
+        }
+        if !simulation.step() {
+            return Ok(Cycle {
+                start: generation - 1,
+                period: 1,
+            });
+        }
+        show(simulation, generation);
+        if let Some(start) = history.insert(simulation.hash, simulation.occupancy(), generation) {
+            return Ok(Cycle {
+                start,
+                period: generation - start,
+            });
+        }
+        generation += 1;
+    }
+}
+
+const PPM_SCALE: usize = 8;
+
+// How to show each generation as the simulation runs
+enum Animation {
+    // Print each generation as text, coloured if stdout is a terminal, pausing in between
+    Text(Duration),
+    // Write each generation as a numbered PPM image in a directory
+    Ppm(PathBuf),
+}
+
+impl Animation {
+    fn show(&self, simulation: &Simulation, generation: usize) -> io::Result<()> {
+        match self {
+            Animation::Text(delay) => {
+                let stdout = io::stdout();
+                let terminal = stdout.is_terminal();
+                let mut out = stdout.lock();
+                if terminal {
+                    // Clear the screen, so each frame replaces the last
+                    write!(out, "\x1b[2J\x1b[H")?;
+                }
+                writeln!(out, "Generation {}", generation)?;
+                writeln!(out, "{}", simulation.render(terminal))?;
+                out.flush()?;
+                thread::sleep(*delay);
+            }
+            Animation::Ppm(dir) => {
+                let path = dir.join(format!("generation{:05}.ppm", generation));
+                simulation.write_ppm(BufWriter::new(File::create(path)?), PPM_SCALE)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 fn parse_grid(input: &str) -> IResult<&str, Grid> {
     map(
         all_consuming(terminated(
-            separated_list1(
-                newline,
-                many1(map(one_of(".L#"), |c| c.try_into().unwrap())),
+            verify(
+                separated_list1(
+                    newline,
+                    many1(map(one_of(".L#"), |c| c.try_into().unwrap())),
+                ),
+                // All the rows must be the same length
+                |rows: &Vec<Vec<SeatState>>| rows.iter().all(|row| row.len() == rows[0].len()),
             ),
             opt(newline),
         )),
@@ -179,35 +574,118 @@ fn parse_grid(input: &str) -> IResult<&str, Grid> {
 }
 
 fn main() {
-    let fname = env::args()
-        .skip(1)
-        .next()
-        .expect("Please provide path to input data!");
+    let mut args = env::args().skip(1);
+    let fname = args.next().expect("Please provide path to input data!");
+    // By default run part 2. The tolerance defaults to 4 for the adjacent neighbourhood,
+    // as in part 1, and 5 for any other.
+    let mut neighbourhood_name = "line-of-sight".to_string();
+    let mut tolerance = None;
+    let mut birth_condition = 0;
+    // --animate prints each generation, waiting --delay milliseconds between them, and
+    // --ppm writes each generation as an image in the given directory
+    let mut animate = false;
+    let mut ppm_dir = None;
+    let mut delay = 100;
+    // Give up if the layout hasn't settled or started repeating after this many generations
+    let mut max_generations = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().expect("Please provide a value for the option!");
+        match arg.as_str() {
+            "--neighbourhood" => neighbourhood_name = value(),
+            "--tolerance" => tolerance = Some(value().parse().expect("Bad tolerance")),
+            "--birth" => birth_condition = value().parse().expect("Bad birth condition"),
+            "--animate" => animate = true,
+            "--ppm" => ppm_dir = Some(PathBuf::from(value())),
+            "--delay" => delay = value().parse().expect("Bad delay"),
+            "--max-generations" => {
+                max_generations = Some(value().parse().expect("Bad generation count"))
+            }
+            _ => panic!("Unknown option '{}'", arg),
+        }
+    }
+    let neighbourhood = parse_neighbourhood(&neighbourhood_name).unwrap();
+    let default_rules = if neighbourhood_name == "adjacent" {
+        SeatRules::PART1
+    } else {
+        SeatRules::PART2
+    };
+    let rules = SeatRules {
+        tolerance: tolerance.unwrap_or(default_rules.tolerance),
+        birth_condition,
+    };
+
     let input = fs::read_to_string(fname).unwrap();
-    let mut last = parse_grid(&input).unwrap().1;
-    let mut gen_count = 1;
-    loop {
-        let next = last.generate(true); // part2
-        if next == last {
-            println!("Grid stable at generation {}!", gen_count);
-            let occupied_count = next
-                .0
-                .iter()
-                .flatten()
-                .filter(|s| **s == SeatState::Occupied)
-                .count();
-            println!("Occupied count: {}", occupied_count);
-            return;
+    let grid = match input.parse::<Grid>() {
+        Ok(grid) => grid,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut simulation = Simulation::new(&grid, neighbourhood.as_ref(), rules);
+    let animation = match ppm_dir {
+        Some(dir) => {
+            fs::create_dir_all(&dir).expect("Couldn't create the image directory");
+            Some(Animation::Ppm(dir))
+        }
+        None if animate => Some(Animation::Text(Duration::from_millis(delay))),
+        None => None,
+    };
+    let show = |simulation: &Simulation, generation| {
+        if let Some(animation) = &animation {
+            animation
+                .show(simulation, generation)
+                .expect("Couldn't show the generation");
+        }
+    };
+    match run(&mut simulation, max_generations, show) {
+        Ok(Cycle { start, period: 1 }) => println!("Grid stable at generation {}!", start + 1),
+        Ok(Cycle { start, period }) => println!(
+            "Grid repeats every {} generations, from generation {}!",
+            period, start
+        ),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-        last = next;
-        gen_count += 1;
     }
+    println!("Occupied count: {}", simulation.occupied());
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Compare two grids, failing with the grids side by side and the cells which differ
+    // marked with 'X' if they aren't the same
+    fn assert_grid_eq(actual: &Grid, expected: &Grid) {
+        if actual == expected {
+            return;
+        }
+        let (actual_text, expected_text) = (actual.to_string(), expected.to_string());
+        let mut message = String::from("grids differ (actual, expected, differences):\n");
+        for (y, (a, e)) in actual_text.lines().zip(expected_text.lines()).enumerate() {
+            let marks: String = (0..a.len().max(e.len()))
+                .map(|x| {
+                    if actual.get(x, y) == expected.get(x, y) {
+                        ' '
+                    } else {
+                        'X'
+                    }
+                })
+                .collect();
+            message.push_str(&format!("{}  {}  {}\n", a, e, marks));
+        }
+        if actual.0.len() != expected.0.len() {
+            message.push_str(&format!(
+                "and they have {} and {} rows\n",
+                actual.0.len(),
+                expected.0.len()
+            ));
+        }
+        panic!("{}", message);
+    }
+
     #[test]
     fn test_generate_part1() {
         let gen1 = parse_grid(
@@ -255,8 +733,8 @@ L.L.L..L..
         .unwrap()
         .1;
 
-        assert_eq!(gen1.generate(false), gen2);
-        assert_eq!(gen2.generate(false), gen3);
+        assert_grid_eq(&gen1.generate(&Adjacent, SeatRules::PART1), &gen2);
+        assert_grid_eq(&gen2.generate(&Adjacent, SeatRules::PART1), &gen3);
     }
     #[test]
     fn test_count_occupied_part1() {
@@ -275,10 +753,10 @@ L.L.L..L..
         .unwrap()
         .1;
 
-        assert_eq!(grid.count_occupied_neighbors(0, 0, false), 2);
-        assert_eq!(grid.count_occupied_neighbors(1, 0, false), 5);
-        assert_eq!(grid.count_occupied_neighbors(2, 1, false), 5);
-        assert_eq!(grid.count_occupied_neighbors(4, 8, false), 8);
+        assert_eq!(grid.count_occupied_neighbors(0, 0, &Adjacent), 2);
+        assert_eq!(grid.count_occupied_neighbors(1, 0, &Adjacent), 5);
+        assert_eq!(grid.count_occupied_neighbors(2, 1, &Adjacent), 5);
+        assert_eq!(grid.count_occupied_neighbors(4, 8, &Adjacent), 8);
     }
 
     #[test]
@@ -293,8 +771,201 @@ L.L.L..L..
 #........
 ...#.....").unwrap().1;
 
-        assert_eq!(grid.count_occupied_neighbors(3, 4, true), 8);
-        assert_eq!(grid.count_occupied_neighbors(0, 1, true), 3);
+        assert_eq!(grid.count_occupied_neighbors(3, 4, &LineOfSight), 8);
+        assert_eq!(grid.count_occupied_neighbors(0, 1, &LineOfSight), 3);
+    }
+
+    #[test]
+    fn test_neighbourhoods() {
+        let grid = parse_grid(
+            "#.#.#
+.###.
+##L##
+.###.
+#.#.#",
+        )
+        .unwrap()
+        .1;
+
+        assert_eq!(grid.count_occupied_neighbors(2, 2, &Adjacent), 8);
+        assert_eq!(grid.count_occupied_neighbors(2, 2, &VonNeumann), 4);
+        assert_eq!(grid.count_occupied_neighbors(2, 2, &Radius(2)), 16);
+        assert_eq!(grid.count_occupied_neighbors(0, 0, &VonNeumann), 0);
+        assert_eq!(grid.count_occupied_neighbors(0, 0, &Radius(2)), 5);
+        // A radius much bigger than the grid covers all of it
+        assert_eq!(grid.count_occupied_neighbors(2, 2, &Radius(usize::MAX)), 16);
+        assert_eq!(Radius(100000).neighbours(&grid, 4, 4).len(), 24);
+        assert_eq!(grid.count_occupied_neighbors(0, 0, &LineOfSight), 3);
+
+        // The centre fills even though its 4 neighbours are occupied, and none of them
+        // has 4 occupied neighbours of its own, so none empties
+        let rules = SeatRules {
+            tolerance: 4,
+            birth_condition: 4,
+        };
+        assert_grid_eq(
+            &grid.generate(&VonNeumann, rules),
+            &"#.#.#
+.###.
+#####
+.###.
+#.#.#"
+                .parse()
+                .unwrap(),
+        );
+        assert!(parse_neighbourhood("radius:3").is_ok());
+        assert!(parse_neighbourhood("radius:x").is_err());
+        assert!(parse_neighbourhood("moore").is_err());
+    }
+
+    #[test]
+    fn test_simulation() {
+        let grid = parse_grid(
+            "L.LL.LL.LL
+LLLLLLL.LL
+L.L.L..L..
+LLLL.LL.LL
+L.LL.LL.LL
+L.LLLLL.LL
+..L.L.....
+LLLLLLLLLL
+L.LLLLLL.L
+L.LLLLL.LL",
+        )
+        .unwrap()
+        .1;
+
+        for (neighbourhood, rules, occupied) in [
+            (&Adjacent as &dyn Neighbourhood, SeatRules::PART1, 37),
+            (&LineOfSight, SeatRules::PART2, 26),
+            (
+                &Radius(2),
+                SeatRules {
+                    tolerance: 9,
+                    birth_condition: 1,
+                },
+                30,
+            ),
+        ]
+        .iter()
+        {
+            // The simulation should match the simple generation step at every step
+            let mut simulation = Simulation::new(&grid, *neighbourhood, *rules);
+            let mut last = grid.generate(*neighbourhood, *rules);
+            while simulation.step() {
+                assert_eq!(simulation.cells, last.0.concat());
+                last = last.generate(*neighbourhood, *rules);
+            }
+            assert_eq!(simulation.cells, last.0.concat());
+            assert_eq!(simulation.occupied(), *occupied);
+        }
+    }
+
+    #[test]
+    fn test_run() {
+        let grid = parse_grid(
+            "L.LL.LL.LL
+LLLLLLL.LL
+L.L.L..L..
+LLLL.LL.LL
+L.LL.LL.LL
+L.LLLLL.LL
+..L.L.....
+LLLLLLLLLL
+L.LLLLLL.L
+L.LLLLL.LL",
+        )
+        .unwrap()
+        .1;
+        let mut simulation = Simulation::new(&grid, &LineOfSight, SeatRules::PART2);
+        let mut shown = vec![];
+        let cycle = run(&mut simulation, None, |_, generation| {
+            shown.push(generation)
+        });
+        assert_eq!(
+            cycle,
+            Ok(Cycle {
+                start: 6,
+                period: 1
+            })
+        );
+        assert_eq!(shown, vec![0, 1, 2, 3, 4, 5, 6]);
+
+        // Two seats which can't stand a single neighbour fill and empty in turn
+        let rules = SeatRules {
+            tolerance: 1,
+            birth_condition: 0,
+        };
+        let grid = parse_grid(".LL.").unwrap().1;
+        let mut simulation = Simulation::new(&grid, &VonNeumann, rules);
+        assert_eq!(
+            run(&mut simulation, None, |_, _| {}),
+            Ok(Cycle {
+                start: 0,
+                period: 2
+            })
+        );
+        let mut simulation = Simulation::new(&grid, &VonNeumann, rules);
+        assert_eq!(
+            run(&mut simulation, Some(1), |_, _| {}),
+            Err("No repeated generation within 1 generations".to_string())
+        );
+
+        // Generations only repeat if they're the same, not just if their hashes are
+        let mut history = History::default();
+        assert_eq!(history.insert(7, vec![0b01], 0), None);
+        assert_eq!(history.insert(7, vec![0b10], 1), None);
+        assert_eq!(history.insert(7, vec![0b10], 2), Some(1));
+        assert_eq!(history.insert(8, vec![0b01], 3), None);
+        let grid = parse_grid("#L.\n.L#").unwrap().1;
+        let simulation = Simulation::new(&grid, &Adjacent, SeatRules::PART1);
+        assert_eq!(simulation.occupancy(), vec![0b100001]);
+    }
+
+    #[test]
+    fn test_render() {
+        let layout = "#.L
+LL#
+";
+        let grid = parse_grid(layout).unwrap().1;
+        let simulation = Simulation::new(&grid, &Adjacent, SeatRules::PART1);
+        assert_eq!(simulation.render(false), layout);
+        assert!(simulation
+            .render(true)
+            .starts_with("\x1b[31m#\x1b[0m\x1b[90m.\x1b[0m\x1b[32mL\x1b[0m\n"));
+
+        let mut ppm = vec![];
+        simulation.write_ppm(&mut ppm, 2).unwrap();
+        let header = b"P6\n6 4\n255\n";
+        assert_eq!(&ppm[..header.len()], header);
+        assert_eq!(ppm.len(), header.len() + 6 * 4 * 3);
+        // The second pixel of the first row is still the occupied seat
+        assert_eq!(ppm[header.len() + 3..header.len() + 6], [220, 50, 47]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "#.LL.L#.##
+#LLLLLL.L#
+L.L.L..L..
+";
+        let grid: Grid = text.parse().unwrap();
+        assert_eq!(grid.to_string(), text);
+        assert_grid_eq(&grid.to_string().parse().unwrap(), &grid);
+
+        assert_eq!(
+            "L.L\nLL\n".parse::<Grid>(),
+            Err("Row 2 has 2 seats, but the first row has 3".to_string())
+        );
+        assert!("L.L\nLxL\n".parse::<Grid>().is_err());
+        assert!("L.L\n\nLLL\n".parse::<Grid>().is_err());
+        assert!(parse_grid("L.L\nLLLL").is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "#.  #L   X")]
+    fn test_assert_grid_eq() {
+        assert_grid_eq(&"#.\nLL".parse().unwrap(), &"#L\nLL".parse().unwrap());
     }
 
     #[test]
@@ -1,14 +1,14 @@
-#![feature(destructuring_assignment)]
 use nom::branch::alt;
 use nom::bytes::complete::tag;
 use nom::character::complete::{char, digit1, newline};
-use nom::combinator::{all_consuming, map, map_res};
-use nom::multi::{count, many1};
+use nom::combinator::{all_consuming, map, map_res, verify};
+use nom::multi::many1;
 use nom::sequence::{delimited, preceded, separated_pair, terminated};
 use nom::IResult;
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::env;
+use std::fmt;
 use std::fs;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -30,105 +30,425 @@ impl TryFrom<char> for MaskBit {
     }
 }
 
-type BitMask = [MaskBit; 36];
+// Most significant bit first, one bit for each bit of the word
+type BitMask = Vec<MaskBit>;
+
+// Addresses, values and masks are all held in one of these, so the hardware's words can
+// be any width up to 128 bits. The original docking hardware has 36 bit words.
+type Word = u128;
+const MAX_WIDTH: usize = 128;
+
+// Comparing memory lists every address a floating write went to, which is refused for
+// writes with more floating bits than this
+const MAX_DIFF_BITS: u32 = 20;
 
 #[derive(Debug, Clone, PartialEq)]
 enum Instruction {
     UpdateMask(BitMask),
-    WriteValue(u64, u64),
-}
-
-fn parse_mask(input: &str) -> IResult<&str, Instruction> {
-    map(
-        preceded(
-            tag("mask = "),
-            count(
-                map(alt((char('0'), char('X'), char('1'))), |c| {
-                    c.try_into().unwrap()
-                }),
-                36,
+    WriteValue(Word, Word),
+}
+
+// A mask with exactly one bit for each bit of the word
+fn parse_mask(width: usize) -> impl Fn(&str) -> IResult<&str, Instruction> {
+    move |input| {
+        map(
+            preceded(
+                tag("mask = "),
+                verify(
+                    many1(map(alt((char('0'), char('X'), char('1'))), |c| {
+                        c.try_into().unwrap()
+                    })),
+                    |mask: &BitMask| mask.len() == width,
+                ),
             ),
-        ),
-        |v| Instruction::UpdateMask(v.try_into().unwrap()),
-    )(input)
+            Instruction::UpdateMask,
+        )(input)
+    }
 }
 
-fn parse_mem(input: &str) -> IResult<&str, Instruction> {
-    separated_pair(
-        delimited(tag("mem["), map_res(digit1, str::parse::<u64>), tag("]")),
-        tag(" = "),
-        map_res(digit1, str::parse::<u64>),
-    )(input)
-    .map(|(rem, w)| (rem, Instruction::WriteValue(w.0, w.1)))
+// A write, where both the address and the value have to fit in a word
+fn parse_mem(width: usize) -> impl Fn(&str) -> IResult<&str, Instruction> {
+    move |input| {
+        let word = || {
+            verify(map_res(digit1, str::parse::<Word>), |n: &Word| {
+                n.checked_shr(width as u32).unwrap_or(0) == 0
+            })
+        };
+        separated_pair(delimited(tag("mem["), word(), tag("]")), tag(" = "), word())(input)
+            .map(|(rem, w)| (rem, Instruction::WriteValue(w.0, w.1)))
+    }
 }
 
-fn parse_program(input: &str) -> IResult<&str, Vec<Instruction>> {
-    all_consuming(many1(terminated(alt((parse_mask, parse_mem)), newline)))(input)
+fn parse_program(input: &str, width: usize) -> IResult<&str, Vec<Instruction>> {
+    all_consuming(many1(terminated(
+        alt((parse_mask(width), parse_mem(width))),
+        newline,
+    )))(input)
 }
 
-struct State {
-    // Internally we store the mask as two u64s for ease of computation later on
-    mask_one: u64,
-    mask_zero: u64,
-    mask_x: u64,
-    regs: HashMap<u64, u64>,
-    part2: bool,
+// A set of addresses: the floating bits can take either value, and the rest are as in bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AddressPattern {
+    bits: Word,
+    floating: Word,
 }
 
-impl State {
-    fn new(part2: bool) -> Self {
+impl AddressPattern {
+    fn new(addr: Word, floating: Word) -> Self {
         Self {
-            mask_one: 0,
-            mask_zero: 0,
-            mask_x: 0,
-            regs: HashMap::new(),
-            part2: part2,
+            bits: addr & !floating,
+            floating,
+        }
+    }
+
+    // This wraps around to 0 if all 128 bits float
+    fn len(&self) -> Word {
+        (1 as Word)
+            .checked_shl(self.floating.count_ones())
+            .unwrap_or(0)
+    }
+
+    fn overlaps(&self, other: &AddressPattern) -> bool {
+        let fixed = !self.floating & !other.floating;
+        (self.bits ^ other.bits) & fixed == 0
+    }
+
+    // The addresses in this pattern which aren't in other, as disjoint patterns
+    fn subtract(&self, other: &AddressPattern) -> Vec<AddressPattern> {
+        if !self.overlaps(other) {
+            return vec![*self];
         }
+        // Fix each bit which floats here but not in other in turn, splitting off the half
+        // with the bit other doesn't have. What's left at the end is all inside other.
+        let mut pieces = vec![];
+        let mut rest = *self;
+        let mut to_split = self.floating & !other.floating;
+        while to_split != 0 {
+            let bit = to_split & to_split.wrapping_neg(); // the lowest bit left
+            to_split &= !bit;
+            rest.floating &= !bit;
+            pieces.push(AddressPattern {
+                bits: rest.bits | (!other.bits & bit),
+                floating: rest.floating,
+            });
+            rest.bits |= other.bits & bit;
+        }
+        pieces
+    }
+}
+
+// The bottom width bits as they're written in a mask: '1' or '0', or 'X' where they float
+fn ternary(bits: Word, floating: Word, width: usize) -> String {
+    (0..width)
+        .rev()
+        .map(|i| match (floating >> i & 1, bits >> i & 1) {
+            (1, _) => 'X',
+            (_, 1) => '1',
+            _ => '0',
+        })
+        .collect()
+}
+
+// Memory written through floating addresses. Rather than every address, it keeps the
+// patterns written to, cutting each one down as later writes overlap it so that they're
+// all disjoint.
+#[derive(Default)]
+struct FloatingMemory {
+    regions: Vec<(AddressPattern, Word)>,
+}
+
+impl FloatingMemory {
+    fn write(&mut self, pattern: AddressPattern, val: Word) {
+        self.regions = self
+            .regions
+            .iter()
+            .flat_map(|(region, val)| {
+                region
+                    .subtract(&pattern)
+                    .into_iter()
+                    .map(move |piece| (piece, *val))
+            })
+            .collect();
+        self.regions.push((pattern, val));
+    }
+
+    // Like the hardware's own adder, this wraps around when it overflows a 128 bit word
+    fn sum(&self) -> Word {
+        self.regions.iter().fold(0, |sum, (region, val)| {
+            sum.wrapping_add(region.len().wrapping_mul(*val))
+        })
+    }
+
+    fn dump(&self) -> Vec<(AddressPattern, Word)> {
+        let mut contents = self.regions.clone();
+        contents.sort_unstable_by_key(|(region, _)| (region.bits, region.floating));
+        contents
+    }
+}
+
+// How many addresses a pattern refers to, or the address itself if there's only one
+fn describe(pattern: &AddressPattern) -> String {
+    match pattern.floating.count_ones() {
+        0 => format!("address {}", pattern.bits),
+        bits if bits < Word::BITS => format!("{} addresses", pattern.len()),
+        bits => format!("2^{} addresses", bits),
     }
+}
+
+// Internally we store the mask as words for ease of computation later on. Until the
+// first mask is set, the mask is empty and changes nothing.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Mask {
+    one: Word,
+    zero: Word,
+    x: Word,
+    width: usize,
+}
 
-    fn execute(&mut self, inst: &Instruction) {
+impl fmt::Display for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", ternary(self.one, self.x, self.width))
+    }
+}
+
+impl From<&BitMask> for Mask {
+    fn from(mask: &BitMask) -> Mask {
+        let empty = Mask {
+            width: mask.len(),
+            ..Mask::default()
+        };
+        mask.iter()
+            .rev()
+            .enumerate()
+            .fold(empty, |mask, (i, bit)| match bit {
+                MaskBit::One => Mask {
+                    one: mask.one | 1 << i,
+                    ..mask
+                },
+                MaskBit::Zero => Mask {
+                    zero: mask.zero | 1 << i,
+                    ..mask
+                },
+                MaskBit::X => Mask {
+                    x: mask.x | 1 << i,
+                    ..mask
+                },
+            })
+    }
+}
+
+// A version of the decoder chip, which decides how the mask applies to each write
+trait Decoder {
+    // Store a value through the mask, returning the addresses and value actually stored
+    fn write(&mut self, mask: &Mask, addr: Word, val: Word) -> (AddressPattern, Word);
+    fn sum(&self) -> Word;
+    // The disjoint patterns of addresses which have been written to and their values, in
+    // address order
+    fn dump(&self) -> Vec<(AddressPattern, Word)>;
+}
+
+// Version 1 (part 1) masks the value written
+#[derive(Default)]
+struct V1 {
+    regs: HashMap<Word, Word>,
+}
+
+impl Decoder for V1 {
+    fn write(&mut self, mask: &Mask, addr: Word, val: Word) -> (AddressPattern, Word) {
+        // mask mutates value. The "ones" set bits, the "zeroes" clear them
+        let val = (val | mask.one) & !mask.zero;
+        self.regs.insert(addr, val);
+        (AddressPattern::new(addr, 0), val)
+    }
+
+    fn sum(&self) -> Word {
+        self.regs
+            .values()
+            .fold(0, |sum, val| sum.wrapping_add(*val))
+    }
+
+    fn dump(&self) -> Vec<(AddressPattern, Word)> {
+        let mut contents = self
+            .regs
+            .iter()
+            .map(|(addr, val)| (AddressPattern::new(*addr, 0), *val))
+            .collect::<Vec<_>>();
+        contents.sort_unstable_by_key(|(pattern, _)| pattern.bits);
+        contents
+    }
+}
+
+// Version 2 (part 2) masks the address written to, which can make it float
+#[derive(Default)]
+struct V2 {
+    memory: FloatingMemory,
+}
+
+impl Decoder for V2 {
+    fn write(&mut self, mask: &Mask, addr: Word, val: Word) -> (AddressPattern, Word) {
+        // mask mutates addr. The "ones" set bits, "zeros" are ignored,
+        // and "x"s float.
+        let addresses = AddressPattern::new(addr | mask.one, mask.x);
+        self.memory.write(addresses, val);
+        (addresses, val)
+    }
+
+    fn sum(&self) -> Word {
+        self.memory.sum()
+    }
+
+    fn dump(&self) -> Vec<(AddressPattern, Word)> {
+        self.memory.dump()
+    }
+}
+
+// A record of one write, for tracing a program
+#[derive(Debug, Clone, PartialEq)]
+struct WriteTrace {
+    mask: Mask,
+    addr: Word,
+    val: Word,
+    addresses: AddressPattern,
+    stored: Word,
+}
+
+impl fmt::Display for WriteTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "mem[{}] = {} with mask {}: stored {} at {}",
+            self.addr,
+            self.val,
+            self.mask,
+            self.stored,
+            ternary(
+                self.addresses.bits,
+                self.addresses.floating,
+                self.mask.width
+            )
+        )?;
+        // Only list the addresses if there aren't too many. The count is decided by how
+        // many bits float, as len() wraps around when all 128 of them do.
+        match self.addresses.floating.count_ones() {
+            bits if bits > 0 && bits <= 4 => {
+                let addresses = permutate(self.addresses.bits, self.addresses.floating);
+                let addresses = addresses.iter().map(Word::to_string).collect::<Vec<_>>();
+                write!(f, " ({} addresses: {})", 1 << bits, addresses.join(", "))
+            }
+            _ => write!(f, " ({})", describe(&self.addresses)),
+        }
+    }
+}
+
+struct State<D> {
+    mask: Mask,
+    decoder: D,
+}
+
+impl<D: Decoder> State<D> {
+    fn new(decoder: D) -> Self {
+        Self {
+            mask: Mask::default(),
+            decoder,
+        }
+    }
+
+    // Execute an instruction, returning a record of it if it was a write
+    fn execute(&mut self, inst: &Instruction) -> Option<WriteTrace> {
         match inst {
             Instruction::UpdateMask(mask) => {
-                (self.mask_one, self.mask_zero, self.mask_x) =
-                    mask.iter()
-                        .rev()
-                        .enumerate()
-                        .fold((0, 0, 0), |(one, zero, x), (i, bit)| match bit {
-                            MaskBit::One => (one | 1 << i, zero, x),
-                            MaskBit::Zero => (one, zero | 1 << i, x),
-                            MaskBit::X => (one, zero, x | 1 << i),
-                        });
+                self.mask = mask.into();
+                None
             }
             Instruction::WriteValue(addr, val) => {
-                if self.part2 {
-                    // mask mutates addr. The "ones" set bits, "zeros" are ignored,
-                    // and "x"s cause permutation.
-                    for mutated_addr in permutate(addr | self.mask_one, self.mask_x) {
-                        self.regs.insert(mutated_addr, *val);
-                    }
-                } else {
-                    // mask mutates value. The "ones" set bits, the "zeroes" clear them
-                    self.regs
-                        .insert(*addr, (val | self.mask_one) & !self.mask_zero);
-                }
+                let (addresses, stored) = self.decoder.write(&self.mask, *addr, *val);
+                Some(WriteTrace {
+                    mask: self.mask,
+                    addr: *addr,
+                    val: *val,
+                    addresses,
+                    stored,
+                })
             }
         }
     }
 }
 
-fn exec_prog(prog: &Vec<Instruction>, part2: bool) -> u64 {
-    let mut state = State::new(part2);
+// Run a program, passing a record of each write to trace, and return the decoder with the
+// final state of memory
+fn exec_prog<D: Decoder>(prog: &[Instruction], decoder: D, mut trace: impl FnMut(WriteTrace)) -> D {
+    let mut state = State::new(decoder);
     for inst in prog {
-        state.execute(inst);
+        if let Some(write) = state.execute(inst) {
+            trace(write);
+        }
+    }
+    state.decoder
+}
+
+// Every address in a dump of memory with its value, in order, unless a pattern has too
+// many floating bits to list them all
+fn addresses(contents: &[(AddressPattern, Word)]) -> Option<Vec<(Word, Word)>> {
+    if contents
+        .iter()
+        .any(|(pattern, _)| pattern.floating.count_ones() > MAX_DIFF_BITS)
+    {
+        return None;
+    }
+    let mut addresses = contents
+        .iter()
+        .flat_map(|(pattern, val)| {
+            permutate(pattern.bits, pattern.floating)
+                .into_iter()
+                .map(move |addr| (addr, *val))
+        })
+        .collect::<Vec<_>>();
+    addresses.sort_unstable();
+    Some(addresses)
+}
+
+// The addresses where two lists of addresses and values differ, with their value in each
+fn diff(a: &[(Word, Word)], b: &[(Word, Word)]) -> Vec<(Word, Option<Word>, Option<Word>)> {
+    let mut differences = vec![];
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    loop {
+        let difference = match (a.peek(), b.peek()) {
+            (None, None) => return differences,
+            (Some((addr_a, val_a)), Some((addr_b, val_b))) if addr_a == addr_b => {
+                a.next();
+                b.next();
+                if val_a == val_b {
+                    continue;
+                }
+                (*addr_a, Some(*val_a), Some(*val_b))
+            }
+            (Some((addr_a, val_a)), Some((addr_b, _))) if addr_a < addr_b => {
+                a.next();
+                (*addr_a, Some(*val_a), None)
+            }
+            (Some((addr_a, val_a)), None) => {
+                a.next();
+                (*addr_a, Some(*val_a), None)
+            }
+            (_, Some((addr_b, val_b))) => {
+                b.next();
+                (*addr_b, None, Some(*val_b))
+            }
+        };
+        differences.push(difference);
     }
-    state.regs.values().sum()
 }
 
-fn permutate(value: u64, permute_mask: u64) -> Vec<u64> {
+fn show(val: Option<Word>) -> String {
+    val.map_or("-".to_string(), |val| val.to_string())
+}
+
+// Every address a floating address can refer to
+fn permutate(value: Word, permute_mask: Word) -> Vec<Word> {
     let mut to_do = vec![(value, permute_mask)];
     let mut done = vec![];
     'outer: while let Some((value, mask)) = to_do.pop() {
-        for i in 0..64 {
+        for i in 0..MAX_WIDTH {
             if ((1 << i) & mask) > 0 {
                 // if the mask bit is set, permute this bit
                 to_do.push((value | (1 << i), mask & !(1 << i))); // bit set
@@ -143,15 +463,102 @@ fn permutate(value: u64, permute_mask: u64) -> Vec<u64> {
 }
 
 fn main() {
-    let fname = env::args()
-        .skip(1)
-        .next()
-        .expect("Please provide path to input file!");
-    let prog = parse_program(&fs::read_to_string(fname).unwrap())
-        .unwrap()
-        .1;
-    println!("part 1: {}", exec_prog(&prog, false)); // Part1
-    println!("part 2: {}", exec_prog(&prog, true)); // Part2
+    let mut args = env::args().skip(1);
+    let fname = args.next().expect("Please provide path to input file!");
+    // --trace shows each write as it happens, --dump shows memory at the end, and --diff
+    // shows where memory ends up different with each decoder. --width sets the number of
+    // bits in a word.
+    let (mut trace, mut dump, mut compare) = (false, false, false);
+    let mut width = 36;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = true,
+            "--dump" => dump = true,
+            "--diff" => compare = true,
+            "--width" => {
+                width = args
+                    .next()
+                    .and_then(|width| width.parse().ok())
+                    .filter(|width| (1..=MAX_WIDTH).contains(width))
+                    .expect("Please provide a word width from 1 to 128 bits!")
+            }
+            _ => panic!("Unknown option '{}'", arg),
+        }
+    }
+    let input = fs::read_to_string(fname).unwrap();
+    let prog = match parse_program(&input, width) {
+        Ok((_, prog)) => prog,
+        // Parsing stops at the first line which isn't an instruction for this width
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            let line = input[..input.len() - e.input.len()].lines().count() + 1;
+            eprintln!(
+                "Line {} isn't a valid instruction for {} bit words: '{}'",
+                line,
+                width,
+                e.input.lines().next().unwrap_or("")
+            );
+            std::process::exit(1);
+        }
+        Err(e) => panic!("{}", e),
+    };
+    // Enough hex and decimal digits for the largest address
+    let max_addr = Word::MAX >> (MAX_WIDTH - width);
+    let hex_digits = format!("{:#x}", max_addr).len();
+    let decimal_digits = max_addr.to_string().len();
+
+    let show_write = |write: WriteTrace| {
+        if trace {
+            println!("{}", write);
+        }
+    };
+    // Memory is dumped as the patterns written to, as there can be far too many addresses
+    // to list them all
+    let show_dump = |contents: &[(AddressPattern, Word)]| {
+        if dump {
+            for (pattern, val) in contents {
+                println!(
+                    "{} = {} ({})",
+                    ternary(pattern.bits, pattern.floating, width),
+                    val,
+                    describe(pattern)
+                );
+            }
+        }
+    };
+    let v1 = exec_prog(&prog, V1::default(), show_write);
+    let v1_contents = if dump || compare { v1.dump() } else { vec![] };
+    show_dump(&v1_contents);
+    println!("part 1: {}", v1.sum());
+    let v2 = exec_prog(&prog, V2::default(), show_write);
+    let v2_contents = if dump || compare { v2.dump() } else { vec![] };
+    show_dump(&v2_contents);
+    println!("part 2: {}", v2.sum());
+
+    if compare {
+        let (v1_addresses, v2_addresses) = match (addresses(&v1_contents), addresses(&v2_contents))
+        {
+            (Some(v1), Some(v2)) => (v1, v2),
+            _ => {
+                eprintln!(
+                    "Can't compare memory with writes to more than 2^{} addresses at once",
+                    MAX_DIFF_BITS
+                );
+                std::process::exit(1);
+            }
+        };
+        println!("Differences between v1 and v2:");
+        for (addr, v1_val, v2_val) in diff(&v1_addresses, &v2_addresses) {
+            println!(
+                "{:#0h$x} {:>d$}: {} / {}",
+                addr,
+                addr,
+                show(v1_val),
+                show(v2_val),
+                h = hex_digits,
+                d = decimal_digits
+            );
+        }
+    }
 }
 
 #[cfg(test)]
@@ -166,11 +573,11 @@ mod tests {
             "mem[7] = 101\n",
             "mem[8] = 0\n",
         );
-        let mut exp_mask = [MaskBit::X; 36];
+        let mut exp_mask = vec![MaskBit::X; 36];
         exp_mask[29] = MaskBit::One;
         exp_mask[34] = MaskBit::Zero;
         assert_eq!(
-            parse_program(input),
+            parse_program(input, 36),
             Ok((
                 "",
                 vec!(
@@ -185,23 +592,23 @@ mod tests {
 
     #[test]
     fn test_state_part1() {
-        let mut state = State::new(false);
+        let mut state = State::new(V1::default());
         state.execute(&Instruction::WriteValue(12, 69));
-        assert_eq!(state.regs[&12], 69);
+        assert_eq!(state.decoder.regs[&12], 69);
 
-        let mut mask = [MaskBit::X; 36];
+        let mut mask = vec![MaskBit::X; 36];
         mask[34] = MaskBit::Zero;
         mask[29] = MaskBit::One;
         state.execute(&Instruction::UpdateMask(mask));
-        assert_eq!(state.mask_one, 1 << 6);
-        assert_eq!(state.mask_zero, 1 << 1);
+        assert_eq!(state.mask.one, 1 << 6);
+        assert_eq!(state.mask.zero, 1 << 1);
 
         state.execute(&Instruction::WriteValue(8, 11));
-        assert_eq!(state.regs[&8], 73);
+        assert_eq!(state.decoder.regs[&8], 73);
         state.execute(&Instruction::WriteValue(7, 101));
-        assert_eq!(state.regs[&7], 101);
+        assert_eq!(state.decoder.regs[&7], 101);
         state.execute(&Instruction::WriteValue(8, 0));
-        assert_eq!(state.regs[&8], 64);
+        assert_eq!(state.decoder.regs[&8], 64);
     }
 
     #[test]
@@ -213,8 +620,8 @@ mod tests {
             "mem[8] = 0\n",
         );
 
-        let prog = parse_program(input).unwrap().1;
-        assert_eq!(exec_prog(&prog, false), 165);
+        let prog = parse_program(input, 36).unwrap().1;
+        assert_eq!(exec_prog(&prog, V1::default(), |_| {}).sum(), 165);
     }
 
     #[test]
@@ -240,7 +647,174 @@ mod tests {
             "mem[26] = 1\n",
         );
 
-        let prog = parse_program(input).unwrap().1;
-        assert_eq!(exec_prog(&prog, true), 208);
+        let prog = parse_program(input, 36).unwrap().1;
+        assert_eq!(exec_prog(&prog, V2::default(), |_| {}).sum(), 208);
+    }
+
+    #[test]
+    fn test_trace_and_dump() {
+        let input = concat!(
+            "mask = 000000000000000000000000000000X1001X\n",
+            "mem[42] = 100\n",
+            "mask = 00000000000000000000000000000000X0XX\n",
+            "mem[26] = 1\n",
+        );
+        let prog = parse_program(input, 36).unwrap().1;
+        let mut writes = vec![];
+        let v2 = exec_prog(&prog, V2::default(), |write| writes.push(write.to_string()));
+        assert_eq!(
+            writes,
+            vec![
+                "mem[42] = 100 with mask 000000000000000000000000000000X1001X: stored 100 \
+                 at 000000000000000000000000000000X1101X (4 addresses: 26, 27, 58, 59)",
+                "mem[26] = 1 with mask 00000000000000000000000000000000X0XX: stored 1 \
+                 at 00000000000000000000000000000001X0XX (8 addresses: 16, 17, 18, 19, 24, \
+                 25, 26, 27)",
+            ]
+        );
+        let v2 = v2.dump();
+        let patterns = v2
+            .iter()
+            .map(|(pattern, val)| (ternary(pattern.bits, pattern.floating, 6), *val))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            patterns,
+            vec![("01X0XX".to_string(), 1), ("11101X".to_string(), 100)]
+        );
+        let v2 = addresses(&v2).unwrap();
+        assert_eq!(
+            v2,
+            vec![
+                (16, 1),
+                (17, 1),
+                (18, 1),
+                (19, 1),
+                (24, 1),
+                (25, 1),
+                (26, 1),
+                (27, 1),
+                (58, 100),
+                (59, 100)
+            ]
+        );
+
+        let v1 = addresses(&exec_prog(&prog, V1::default(), |_| {}).dump()).unwrap();
+        assert_eq!(v1, vec![(26, 1), (42, 50)]);
+        assert_eq!(
+            diff(&v1, &v2[6..]),
+            vec![
+                (27, None, Some(1)),
+                (42, Some(50), None),
+                (58, None, Some(100)),
+                (59, None, Some(100))
+            ]
+        );
+        assert_eq!(diff(&[(1, 2)], &[(1, 3)]), vec![(1, Some(2), Some(3))]);
+    }
+
+    #[test]
+    fn test_word_width() {
+        // A mask must have a bit for each bit of the word
+        let input = "mask = X1X0\nmem[3] = 15\n";
+        assert!(parse_program(input, 36).is_err());
+        let prog = parse_program(input, 4).unwrap().1;
+        assert_eq!(
+            exec_prog(&prog, V1::default(), |_| {}).dump(),
+            vec![(AddressPattern::new(3, 0), 14)]
+        );
+        assert_eq!(exec_prog(&prog, V2::default(), |_| {}).sum(), 60);
+        // So must addresses and values
+        assert!(parse_program("mask = X1X0\nmem[16] = 15\n", 4).is_err());
+        assert!(parse_program("mask = X1X0\nmem[3] = 16\n", 4).is_err());
+
+        // 128 bit words, floating the top bit and all but the bottom bit
+        let input = format!(
+            "mask = X{}0\nmem[1] = {}\nmask = 1{}\nmem[0] = 2\n",
+            "0".repeat(126),
+            Word::MAX,
+            "X".repeat(127)
+        );
+        let prog = parse_program(&input, 128).unwrap().1;
+        let v1 = exec_prog(&prog, V1::default(), |_| {});
+        assert_eq!(
+            addresses(&v1.dump()),
+            Some(vec![(0, (1 << 127) + 2), (1, 1 << 127)])
+        );
+        // The sum wraps around
+        assert_eq!(v1.sum(), 2);
+        let mut writes = vec![];
+        let v2 = exec_prog(&prog, V2::default(), |write| writes.push(write));
+        assert_eq!(writes[1].addresses.len(), 1 << 127);
+        // Far too many addresses to list for a diff
+        assert_eq!(addresses(&v2.dump()), None);
+        assert!(writes[1]
+            .to_string()
+            .ends_with(" (170141183460469231731687303715884105728 addresses)"));
+        assert_eq!(writes[1].mask.to_string(), format!("1{}", "X".repeat(127)));
+        // Half of all addresses hold 2, which wraps around to 0, and the address with the
+        // top bit clear still holds the first value
+        assert_eq!(v2.sum(), Word::MAX);
+
+        // Every address at once
+        let input = format!("mask = {}\nmem[0] = 1\n", "X".repeat(128));
+        let prog = parse_program(&input, 128).unwrap().1;
+        let mut writes = vec![];
+        let v2 = exec_prog(&prog, V2::default(), |write| writes.push(write.to_string()));
+        let all = "X".repeat(128);
+        assert_eq!(
+            writes,
+            vec![format!(
+                "mem[0] = 1 with mask {}: stored 1 at {} (2^128 addresses)",
+                all, all
+            )]
+        );
+        assert_eq!(v2.sum(), 0);
+    }
+
+    #[test]
+    fn test_address_pattern() {
+        let a = AddressPattern::new(0b0000, 0b1011);
+        assert_eq!(a.len(), 8);
+        assert!(!a.overlaps(&AddressPattern::new(0b0100, 0)));
+        assert_eq!(a.subtract(&AddressPattern::new(0b0100, 0)), vec![a]);
+
+        let b = AddressPattern::new(0b0010, 0b1000);
+        assert!(a.overlaps(&b));
+        let pieces = a.subtract(&b);
+        assert_eq!(pieces.iter().map(AddressPattern::len).sum::<Word>(), 6);
+        let mut addresses = pieces
+            .iter()
+            .flat_map(|piece| permutate(piece.bits, piece.floating))
+            .collect::<Vec<_>>();
+        addresses.sort();
+        assert_eq!(
+            addresses,
+            vec![0b0000, 0b0001, 0b0011, 0b1000, 0b1001, 0b1011]
+        );
+        assert_eq!(b.subtract(&a), vec![]);
+    }
+
+    #[test]
+    fn test_floating_memory() {
+        // Overlapping writes, checked against writing every address
+        let writes = [
+            (0b0000_0000, 0b1010_1011, 3),
+            (0b0000_0100, 0b0000_0011, 5),
+            (0b1000_0000, 0b0111_0000, 7),
+            (0b0000_0001, 0b1000_0000, 0),
+            (0b0000_0000, 0b1111_1111, 1),
+            (0b1111_0000, 0b0000_1100, 9),
+        ];
+        for n in 0..=writes.len() {
+            let mut memory = FloatingMemory::default();
+            let mut regs = HashMap::new();
+            for (addr, floating, val) in &writes[..n] {
+                memory.write(AddressPattern::new(*addr, *floating), *val);
+                for addr in permutate(*addr, *floating) {
+                    regs.insert(addr, *val);
+                }
+            }
+            assert_eq!(memory.sum(), regs.values().sum());
+        }
     }
 }
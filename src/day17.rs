@@ -1,263 +1,438 @@
-use std::collections::HashMap;
-use std::convert::TryFrom;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::env;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::iter::FromIterator;
+use std::str::FromStr;
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum CubeState {
-    Active,
-    Inactive,
+// The coordinates of every active cube. Everything else is inactive, so the pocket
+// dimension can grow in any direction without being resized.
+type Pocket<const N: usize> = HashSet<[i32; N]>;
+
+// This parses our 2d input into the z = 0 (and w = 0, and so on) plane of an N dimensional
+// pocket dimension
+fn parse_input<T, const N: usize>(r: T) -> Pocket<N>
+where
+    T: BufRead,
+{
+    assert!(N >= 2, "The input needs at least 2 dimensions");
+    let mut pocket = HashSet::new();
+    for (y, line) in r.lines().map(Result::unwrap).enumerate() {
+        for (x, c) in line.chars().enumerate() {
+            match c {
+                '#' => {
+                    let mut cube = [0; N];
+                    cube[0] = x as i32;
+                    cube[1] = y as i32;
+                    pocket.insert(cube);
+                }
+                '.' => (),
+                _ => panic!("Unknown cube state '{}'", c),
+            }
+        }
+    }
+    pocket
+}
+
+// The input is a 2d plane, so everything is symmetric in the extra axes: flipping the sign
+// of any of them, or swapping any two, gives the same pocket dimension. Each set of
+// symmetric cubes can be stood in for by the one whose extra coordinates are all
+// non-negative and in ascending order.
+fn canonical<const N: usize>(cube: [i32; N]) -> [i32; N] {
+    let mut cube = cube;
+    for d in cube[2..].iter_mut() {
+        *d = d.abs();
+    }
+    cube[2..].sort_unstable();
+    cube
 }
 
-impl TryFrom<char> for CubeState {
-    type Error = &'static str;
-    fn try_from(c: char) -> Result<CubeState, &'static str> {
-        match c {
-            '.' => Ok(CubeState::Inactive),
-            '#' => Ok(CubeState::Active),
-            _ => Err("No"),
+// How many cubes a canonical cube stands in for: one for each distinct way of ordering its
+// extra coordinates, times two for each one which can be negated
+fn multiplicity<const N: usize>(cube: &[i32; N]) -> usize {
+    let extra = &cube[2..];
+    let mut orderings = (1..=extra.len()).product::<usize>();
+    let mut run = 1;
+    for i in 1..extra.len() {
+        if extra[i] == extra[i - 1] {
+            run += 1;
+            orderings /= run;
+        } else {
+            run = 1;
         }
     }
+    orderings << extra.iter().filter(|d| **d != 0).count()
 }
 
-type Dimension = HashMap<i32, HashMap<i32, HashMap<i32, HashMap<i32, CubeState>>>>;
+// The offset to every neighbour of a cube, which is 3^N - 1 of them
+fn neighbour_offsets<const N: usize>() -> Vec<[i32; N]> {
+    let mut offsets = vec![[0; N]];
+    for axis in 0..N {
+        offsets = offsets
+            .into_iter()
+            .flat_map(|offset| {
+                (-1..=1).map(move |d| {
+                    let mut offset = offset;
+                    offset[axis] = d;
+                    offset
+                })
+            })
+            .collect();
+    }
+    offsets.retain(|offset| offset.iter().any(|d| *d != 0));
+    offsets
+}
 
-fn parse_input<T>(r: T) -> Dimension
-where
-    T: BufRead,
-{
-    // Pretty gross huh? This parses our 2d input and returns a 4d Dimension.
-    let mut m = HashMap::new();
-    m.insert(0, {
-        let mut m = HashMap::new();
-        m.insert(
-            0 as i32,
-            HashMap::from_iter(
-                r.lines()
-                    .map(Result::unwrap)
-                    .map(|l| {
-                        HashMap::from_iter(
-                            l.chars()
-                                .map(CubeState::try_from)
-                                .map(Result::unwrap)
-                                .enumerate()
-                                .map(|(i, s)| (i as i32, s)),
-                        )
-                    })
-                    .enumerate()
-                    .map(|(i, s)| (i as i32, s)),
-            ),
-        );
-        m
-    });
-    m
+fn axis_name(axis: usize) -> String {
+    match axis {
+        0 => "x".to_string(),
+        1 => "y".to_string(),
+        2 => "z".to_string(),
+        3 => "w".to_string(),
+        _ => format!("axis {}", axis + 1),
+    }
 }
 
-fn initmap<T>(min: i32, max: i32, val: T) -> HashMap<i32, T>
-where
-    T: Clone,
-{
-    let mut m = HashMap::new();
-    for i in min..=max {
-        m.insert(i, val.clone());
+// The smallest and largest coordinate of any active cube along each axis
+fn bounds<const N: usize>(pocket: &Pocket<N>) -> Option<[(i32, i32); N]> {
+    let mut cubes = pocket.iter();
+    let first = cubes.next()?;
+    let mut bounds = [(0, 0); N];
+    for axis in 0..N {
+        bounds[axis] = (first[axis], first[axis]);
     }
-    m
+    for cube in cubes {
+        for axis in 0..N {
+            bounds[axis].0 = bounds[axis].0.min(cube[axis]);
+            bounds[axis].1 = bounds[axis].1.max(cube[axis]);
+        }
+    }
+    Some(bounds)
 }
 
-// find the bounds of the dimension, adding an additional "shell" if there are active cubes in
-// the outer shell.
-fn expand(d: &mut Dimension) {
-    // transform the dimension into a vec of (x, y, z, state), and filter for active cubes only
-    let cells = d
-        .iter()
-        .map(|(w, e)| {
-            e.iter().map(move |(z, e)| {
-                e.iter()
-                    .map(move |(y, f)| f.iter().map(move |(x, state)| (*x, *y, *z, *w, state)))
-            })
-        })
-        .flatten()
-        .flatten()
-        .flatten()
-        .filter(|(_, _, _, _, state)| **state == CubeState::Active)
-        .collect::<Vec<(_, _, _, _, _)>>();
-
-    // this relies on all hashmaps being equal length!
-    let max_w = *d.keys().max().unwrap();
-    let min_w = *d.keys().min().unwrap();
-
-    let max_z = *d[&0].keys().max().unwrap();
-    let min_z = *d[&0].keys().min().unwrap();
-
-    let max_y = *d[&0][&0].keys().max().unwrap();
-    let min_y = *d[&0][&0].keys().min().unwrap();
-
-    let max_x = *d[&0][&0][&0].keys().max().unwrap();
-    let min_x = *d[&0][&0][&0].keys().min().unwrap();
-
-    let active_max_x = cells.iter().max_by_key(|(x, _, _, _, _)| x).unwrap().0;
-    let active_min_x = cells.iter().min_by_key(|(x, _, _, _, _)| x).unwrap().0;
-
-    let active_max_y = cells.iter().max_by_key(|(_, y, _, _, _)| y).unwrap().1;
-    let active_min_y = cells.iter().min_by_key(|(_, y, _, _, _)| y).unwrap().1;
-
-    let active_max_z = cells.iter().max_by_key(|(_, _, z, _, _)| z).unwrap().2;
-    let active_min_z = cells.iter().min_by_key(|(_, _, z, _, _)| z).unwrap().2;
-
-    let active_max_w = cells.iter().max_by_key(|(_, _, _, w, _)| w).unwrap().3;
-    let active_min_w = cells.iter().min_by_key(|(_, _, _, w, _)| w).unwrap().3;
-
-    if max_w == active_max_w || min_w == active_min_w {
-        // expand a
-        println!("expanding a (old min/max: {}/{})", min_w, max_w);
-        d.insert(
-            max_w + 1,
-            initmap(
-                min_z,
-                max_z,
-                initmap(min_y, max_y, initmap(min_x, max_x, CubeState::Inactive)),
-            ),
-        );
-        d.insert(
-            min_w - 1,
-            initmap(
-                min_z,
-                max_z,
-                initmap(min_y, max_y, initmap(min_x, max_x, CubeState::Inactive)),
-            ),
-        );
+// A life-like rule: how many active neighbours make an inactive cube become active (birth)
+// and an active cube stay active (survival)
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    birth: BTreeSet<usize>,
+    survival: BTreeSet<usize>,
+}
+
+impl Rule {
+    // Whether a cube is active in the next cycle
+    fn next_state(&self, active: bool, neighbours: usize) -> bool {
+        if active {
+            self.survival.contains(&neighbours)
+        } else {
+            self.birth.contains(&neighbours)
+        }
     }
+}
+
+// The puzzle's rule
+impl Default for Rule {
+    fn default() -> Rule {
+        "B3/S23".parse().unwrap()
+    }
+}
+
+// Birth/survival notation, such as "B3/S23". Each digit is a neighbour count, but with more
+// dimensions there can be more than 9 neighbours, so counts can be separated by commas
+// instead, as in "B3/S2,3,10". Births with no active neighbours would fill the whole
+// infinite pocket dimension, so B0 rules aren't allowed.
+impl FromStr for Rule {
+    type Err = String;
 
-    if max_z == active_max_z || min_z == active_min_z {
-        // expand z
-        println!("expanding z (old min/max: {}/{})", min_z, max_z);
-        for zyx in d.iter_mut() {
-            zyx.1.insert(
-                max_z + 1,
-                initmap(min_y, max_y, initmap(min_x, max_x, CubeState::Inactive)),
-            );
-            zyx.1.insert(
-                min_z - 1,
-                initmap(min_y, max_y, initmap(min_x, max_x, CubeState::Inactive)),
-            );
+    fn from_str(s: &str) -> Result<Rule, String> {
+        let counts = |part: &str, prefix: char| -> Option<BTreeSet<usize>> {
+            let part = part.strip_prefix(prefix)?;
+            if part.contains(',') {
+                part.split(',').map(|n| n.parse().ok()).collect()
+            } else {
+                part.chars()
+                    .map(|c| c.to_digit(10).map(|n| n as usize))
+                    .collect()
+            }
+        };
+        let error = || format!("'{}' isn't a rule like B3/S23", s);
+        let (birth, survival) = s
+            .to_uppercase()
+            .split_once('/')
+            .ok_or_else(error)
+            .map(|(birth, survival)| (counts(birth, 'B'), counts(survival, 'S')))?;
+        match (birth, survival) {
+            (Some(birth), _) if birth.contains(&0) => Err(format!(
+                "'{}' has births with no neighbours, which isn't supported",
+                s
+            )),
+            (Some(birth), Some(survival)) => Ok(Rule { birth, survival }),
+            _ => Err(error()),
         }
     }
+}
 
-    if max_y == active_max_y || min_y == active_min_y {
-        // expand y
-        println!("expanding y (old min/max: {}/{})", min_y, max_y);
-        for zyx in d.iter_mut() {
-            for yx in zyx.1 {
-                yx.1.insert(max_y + 1, initmap(min_x, max_x, CubeState::Inactive));
-                yx.1.insert(min_y - 1, initmap(min_x, max_x, CubeState::Inactive));
+// Rather than visiting every cube and counting its neighbours, each active cube adds one
+// to the count of each of its neighbours. Births need an active neighbour, so only active
+// cubes and cubes next to them can be active in the next cycle, and those are the only ones
+// which get counted.
+fn generate<const N: usize>(pocket: &Pocket<N>, offsets: &[[i32; N]], rule: &Rule) -> Pocket<N> {
+    let mut neighbours: HashMap<[i32; N], usize> = HashMap::new();
+    for cube in pocket {
+        // Active cubes with no active neighbours can still survive
+        neighbours.entry(*cube).or_insert(0);
+        for offset in offsets {
+            let mut neighbour = *cube;
+            for axis in 0..N {
+                neighbour[axis] += offset[axis];
             }
+            *neighbours.entry(neighbour).or_insert(0) += 1;
         }
     }
+    neighbours
+        .into_iter()
+        .filter(|(cube, count)| rule.next_state(pocket.contains(cube), *count))
+        .map(|(cube, _)| cube)
+        .collect()
+}
 
-    if max_x == active_max_x || min_x == active_min_x {
-        // expand x
-        println!("expanding x (old min/max: {}/{})", min_x, max_x);
-        for zyx in d.iter_mut() {
-            for yx in zyx.1 {
-                for x in yx.1 {
-                    x.1.insert(max_x + 1, CubeState::Inactive);
-                    x.1.insert(min_x - 1, CubeState::Inactive);
-                }
+// Like generate, but for a pocket dimension holding only canonical cubes. The neighbours
+// of each canonical cube which are symmetric to one another all land on the same canonical
+// cube, but so do the neighbours of every cube it stands in for. Weighting by how many
+// cubes each one stands in for, and then dividing by how many the neighbour stands in
+// for, gives how many active neighbours that neighbour actually has.
+fn generate_symmetric<const N: usize>(
+    pocket: &Pocket<N>,
+    offsets: &[[i32; N]],
+    rule: &Rule,
+) -> Pocket<N> {
+    let mut neighbours: HashMap<[i32; N], usize> = HashMap::new();
+    for cube in pocket {
+        neighbours.entry(*cube).or_insert(0);
+        let weight = multiplicity(cube);
+        for offset in offsets {
+            let mut neighbour = *cube;
+            for axis in 0..N {
+                neighbour[axis] += offset[axis];
             }
+            *neighbours.entry(canonical(neighbour)).or_insert(0) += weight;
         }
     }
+    neighbours
+        .into_iter()
+        .filter(|(cube, count)| rule.next_state(pocket.contains(cube), count / multiplicity(cube)))
+        .map(|(cube, _)| cube)
+        .collect()
 }
 
-// Count how many neighbors are active
-fn count_neighbors(d: &Dimension, p: (i32, i32, i32, i32)) -> usize {
-    (p.0 - 1..=p.0 + 1)
-        .map(|x| {
-            (p.1 - 1..=p.1 + 1).map(move |y| {
-                (p.2 - 1..=p.2 + 1).map(move |z| (p.3 - 1..=p.3 + 1).map(move |a| (x, y, z, a)))
-            })
-        })
-        .flatten()
-        .flatten()
-        .flatten()
-        .filter_map(|(x, y, z, a)| {
-            if let Some(zxys) = d.get(&a) {
-                if let Some(xys) = zxys.get(&z) {
-                    if let Some(xs) = xys.get(&y) {
-                        if let Some(state) = xs.get(&x) {
-                            if *state == CubeState::Active && (x, y, z, a) != p {
-                                return Some(());
-                            }
-                        }
-                    }
+// The number of active cubes, counting each canonical cube as every cube it stands in for
+fn symmetric_count<const N: usize>(pocket: &Pocket<N>) -> usize {
+    pocket.iter().map(multiplicity).sum()
+}
+
+// Bounds of a pocket dimension holding only canonical cubes, which stretch as far in the
+// negative direction as the positive one along every extra axis
+fn symmetric_bounds<const N: usize>(pocket: &Pocket<N>) -> Option<[(i32, i32); N]> {
+    let mut bounds = bounds(pocket)?;
+    let furthest = bounds[2..].iter().map(|(_, max)| *max).max().unwrap_or(0);
+    for axis in bounds[2..].iter_mut() {
+        *axis = (-furthest, furthest);
+    }
+    Some(bounds)
+}
+
+// Every cube which a pocket dimension holding only canonical cubes stands in for
+fn unfold<const N: usize>(pocket: &Pocket<N>) -> Pocket<N> {
+    let mut unfolded = HashSet::new();
+    for cube in pocket {
+        // Keep negating and swapping extra coordinates until there's nothing new
+        let mut to_do = vec![*cube];
+        while let Some(cube) = to_do.pop() {
+            if !unfolded.insert(cube) {
+                continue;
+            }
+            for axis in 2..N {
+                let mut negated = cube;
+                negated[axis] = -negated[axis];
+                to_do.push(negated);
+                if axis + 1 < N {
+                    let mut swapped = cube;
+                    swapped.swap(axis, axis + 1);
+                    to_do.push(swapped);
                 }
             }
-            None
-        })
-        .count()
+        }
+    }
+    unfolded
+}
+
+// Draw each 2d slice through the pocket dimension in the puzzle's format, headed by its z
+// (and w, and so on) coordinates, with z changing fastest. Only the box around the active
+// cubes is drawn, so every slice is the same size.
+fn render<const N: usize>(pocket: &Pocket<N>) -> String {
+    let bounds = match bounds(pocket) {
+        Some(bounds) => bounds,
+        None => return String::new(),
+    };
+    let mut slices = vec![];
+    let mut slice = [0; N];
+    for (axis, (min, _)) in bounds.iter().enumerate() {
+        slice[axis] = *min;
+    }
+    loop {
+        let mut text = (2..N)
+            .map(|axis| format!("{}={}", axis_name(axis), slice[axis]))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if N > 2 {
+            text.push('\n');
+        }
+        for y in bounds[1].0..=bounds[1].1 {
+            for x in bounds[0].0..=bounds[0].1 {
+                let mut cube = slice;
+                cube[0] = x;
+                cube[1] = y;
+                text.push(if pocket.contains(&cube) { '#' } else { '.' });
+            }
+            text.push('\n');
+        }
+        slices.push(text);
+
+        // Move on to the next slice, like an odometer
+        let mut axis = 2;
+        loop {
+            if axis == N {
+                return slices.join("\n");
+            }
+            if slice[axis] < bounds[axis].1 {
+                slice[axis] += 1;
+                break;
+            }
+            slice[axis] = bounds[axis].0;
+            axis += 1;
+        }
+    }
 }
 
-fn generate(d: &Dimension) -> Dimension {
-    HashMap::from_iter(d.iter().map(|(a, e)| {
-        (
-            *a,
-            HashMap::from_iter(e.iter().map(|(z, f)| {
-                (
-                    *z,
-                    HashMap::from_iter(f.iter().map(move |(y, g)| {
-                        (
-                            *y,
-                            HashMap::from_iter(g.iter().map(move |(x, state)| {
-                                let neighbors = count_neighbors(d, (*x, *y, *z, *a));
-                                (
-                                    *x,
-                                    match state {
-                                        CubeState::Active => {
-                                            if neighbors == 2 || neighbors == 3 {
-                                                CubeState::Active
-                                            } else {
-                                                CubeState::Inactive
-                                            }
-                                        }
-                                        CubeState::Inactive => {
-                                            if neighbors == 3 {
-                                                CubeState::Active
-                                            } else {
-                                                CubeState::Inactive
-                                            }
-                                        }
-                                    },
-                                )
-                            })),
-                        )
-                    })),
-                )
-            })),
-        )
-    }))
+struct Options {
+    symmetric: bool,
+    rule: Rule,
+    cycles: usize,
+    // Draw the pocket dimension before the first cycle and after each one
+    render: bool,
+    // Don't say when the pocket dimension expands
+    quiet: bool,
+}
+
+fn run<const N: usize>(fname: &str, options: &Options) -> usize {
+    // The input only has canonical cubes, so it can be used as it is either way
+    let mut pocket: Pocket<N> = parse_input(BufReader::new(File::open(fname).unwrap()));
+    let offsets = neighbour_offsets::<N>();
+    let symmetric = options.symmetric;
+    let generate = |pocket: &Pocket<N>| {
+        if symmetric {
+            generate_symmetric(pocket, &offsets, &options.rule)
+        } else {
+            generate(pocket, &offsets, &options.rule)
+        }
+    };
+    let bounds = |pocket: &Pocket<N>| {
+        if symmetric {
+            symmetric_bounds(pocket)
+        } else {
+            bounds(pocket)
+        }
+    };
+    let show = |pocket: &Pocket<N>| {
+        if symmetric {
+            println!("{}", render(&unfold(pocket)));
+        } else {
+            println!("{}", render(pocket));
+        }
+    };
+    if options.render {
+        println!("Before any cycles:\n");
+        show(&pocket);
+    }
+    for cycle in 1..=options.cycles {
+        let old = bounds(&pocket);
+        pocket = generate(&pocket);
+        if let (Some(old), Some(new), false) = (old, bounds(&pocket), options.quiet) {
+            for axis in 0..N {
+                if new[axis].0 < old[axis].0 || new[axis].1 > old[axis].1 {
+                    println!(
+                        "expanding {} (old min/max: {}/{})",
+                        axis_name(axis),
+                        old[axis].0,
+                        old[axis].1
+                    );
+                }
+            }
+        }
+        if options.render {
+            let plural = if cycle == 1 { "" } else { "s" };
+            println!("\nAfter {} cycle{}:\n", cycle, plural);
+            show(&pocket);
+        }
+    }
+    if symmetric {
+        symmetric_count(&pocket)
+    } else {
+        pocket.len()
+    }
 }
 
 fn main() {
-    let fname = env::args()
-        .skip(1)
-        .next()
-        .expect("Please provide path to input file!");
-    let mut input = parse_input(BufReader::new(File::open(fname).unwrap()));
-    for _ in 0..6 {
-        expand(&mut input);
-        input = generate(&input);
+    let mut args = env::args().skip(1);
+    let fname = args.next().expect("Please provide path to input file!");
+    // --dims sets how many dimensions the pocket dimension has, which is 4 by default.
+    // --symmetric only simulates one of each set of symmetric cubes, which is much faster
+    // with more dimensions. --rule sets the rule in B/S notation, and --cycles how many
+    // cycles to run for. --render draws each slice of the pocket dimension every cycle,
+    // and --quiet stops it saying each time it expands.
+    let mut dims = 4;
+    let mut options = Options {
+        symmetric: false,
+        rule: Rule::default(),
+        cycles: 6,
+        render: false,
+        quiet: false,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dims" => {
+                dims = args
+                    .next()
+                    .and_then(|dims| dims.parse().ok())
+                    .expect("Please provide a number of dimensions!")
+            }
+            "--symmetric" => options.symmetric = true,
+            "--rule" => {
+                options.rule = args
+                    .next()
+                    .expect("Please provide a rule!")
+                    .parse()
+                    .unwrap_or_else(|e| panic!("{}", e))
+            }
+            "--cycles" => {
+                options.cycles = args
+                    .next()
+                    .and_then(|cycles| cycles.parse().ok())
+                    .expect("Please provide a number of cycles!")
+            }
+            "--render" => options.render = true,
+            "--quiet" => options.quiet = true,
+            _ => panic!("Unknown option '{}'", arg),
+        }
     }
-    let active_count = input
-        .iter()
-        .map(|(_, maps)| maps)
-        .flatten()
-        .map(|(_, maps)| maps)
-        .flatten()
-        .map(|(_, maps)| maps)
-        .flatten()
-        .filter(|(_, state)| **state == CubeState::Active)
-        .count();
+    let active_count = match dims {
+        2 => run::<2>(&fname, &options),
+        3 => run::<3>(&fname, &options),
+        4 => run::<4>(&fname, &options),
+        5 => run::<5>(&fname, &options),
+        6 => run::<6>(&fname, &options),
+        7 => run::<7>(&fname, &options),
+        8 => run::<8>(&fname, &options),
+        _ => panic!("Only 2 to 8 dimensions are supported"),
+    };
     println!("Active: {}", active_count);
 }
 
@@ -265,24 +440,151 @@ fn main() {
 mod tests {
     use super::*;
 
+    const INPUT: &str = ".#.
+..#
+###";
+
+    fn cycles<const N: usize>(n: usize) -> usize {
+        let offsets = neighbour_offsets::<N>();
+        let mut pocket: Pocket<N> = parse_input(INPUT.as_bytes());
+        for _ in 0..n {
+            pocket = generate(&pocket, &offsets, &Rule::default());
+        }
+        pocket.len()
+    }
+
     #[test]
     fn test_parse_input() {
-        let input = parse_input(
-            ".#.
+        // The input is the z = 0 plane of the w = 0 cube
+        let pocket: Pocket<4> = parse_input(INPUT.as_bytes());
+        let expected = vec![
+            [1, 0, 0, 0],
+            [2, 1, 0, 0],
+            [0, 2, 0, 0],
+            [1, 2, 0, 0],
+            [2, 2, 0, 0],
+        ];
+        assert_eq!(pocket, expected.into_iter().collect());
+        assert_eq!(bounds(&pocket), Some([(0, 2), (0, 2), (0, 0), (0, 0)]));
+    }
+
+    #[test]
+    fn test_neighbour_offsets() {
+        assert_eq!(neighbour_offsets::<2>().len(), 8);
+        assert_eq!(neighbour_offsets::<3>().len(), 26);
+        assert_eq!(neighbour_offsets::<4>().len(), 80);
+        assert!(!neighbour_offsets::<3>().contains(&[0, 0, 0]));
+    }
+
+    #[test]
+    fn test_generate() {
+        assert_eq!(cycles::<3>(1), 11);
+        assert_eq!(cycles::<3>(6), 112);
+        assert_eq!(cycles::<4>(6), 848);
+        // In 2d this is a glider, which never changes size
+        assert_eq!(cycles::<2>(6), 5);
+    }
+
+    #[test]
+    fn test_rule() {
+        let rule = "B36/S23".parse::<Rule>().unwrap();
+        assert_eq!(rule.birth, vec![3, 6].into_iter().collect());
+        assert_eq!(rule.survival, vec![2, 3].into_iter().collect());
+        assert!(rule.next_state(false, 6));
+        assert!(!rule.next_state(true, 6));
+        assert_eq!("b3/s23".parse(), Ok(Rule::default()));
+        let rule = "B3,12/S".parse::<Rule>().unwrap();
+        assert_eq!(rule.birth, vec![3, 12].into_iter().collect());
+        assert!(rule.survival.is_empty());
+        assert_eq!(
+            "B3S23".parse::<Rule>(),
+            Err("'B3S23' isn't a rule like B3/S23".to_string())
+        );
+        assert!("S23/B3".parse::<Rule>().is_err());
+        assert!("B3/S2x".parse::<Rule>().is_err());
+        assert_eq!(
+            "B03/S23".parse::<Rule>(),
+            Err("'B03/S23' has births with no neighbours, which isn't supported".to_string())
+        );
+
+        // With no births and nothing dying, nothing changes, and with neither everything
+        // dies
+        let offsets = neighbour_offsets::<3>();
+        let pocket: Pocket<3> = parse_input(INPUT.as_bytes());
+        let frozen = "B/S012345678".parse().unwrap();
+        assert_eq!(generate(&pocket, &offsets, &frozen), pocket);
+        assert!(generate(&pocket, &offsets, &"B/S".parse().unwrap()).is_empty());
+
+        // A cube on its own survives with S0, whether or not it's symmetric
+        let alone: Pocket<3> = vec![[0, 0, 0], [5, 5, 5]].into_iter().collect();
+        let rule = "B/S0".parse().unwrap();
+        assert_eq!(generate(&alone, &offsets, &rule), alone);
+        assert_eq!(generate_symmetric(&alone, &offsets, &rule), alone);
+    }
+
+    #[test]
+    fn test_symmetric() {
+        assert_eq!(canonical([1, -2, 3, -1, 0]), [1, -2, 0, 1, 3]);
+        assert_eq!(multiplicity(&[5, 5]), 1);
+        assert_eq!(multiplicity(&[0, 0, 0, 1]), 4);
+        assert_eq!(multiplicity(&[0, 0, 1, 1]), 4);
+        assert_eq!(multiplicity(&[0, 0, 0, 1, 2]), 24);
+        assert_eq!(multiplicity(&[0, 0, 1, 1, 2]), 24);
+
+        // Every cycle, the symmetric pocket dimension stands in for exactly the whole one
+        fn check<const N: usize>() {
+            let offsets = neighbour_offsets::<N>();
+            let mut pocket: Pocket<N> = parse_input(INPUT.as_bytes());
+            let mut symmetric = pocket.clone();
+            for _ in 0..4 {
+                pocket = generate(&pocket, &offsets, &Rule::default());
+                symmetric = generate_symmetric(&symmetric, &offsets, &Rule::default());
+                let canonical = pocket.iter().copied().map(canonical).collect::<Pocket<N>>();
+                assert_eq!(symmetric, canonical);
+                assert_eq!(symmetric_count(&symmetric), pocket.len());
+                assert_eq!(symmetric_bounds(&symmetric), bounds(&pocket));
+                assert_eq!(unfold(&symmetric), pocket);
+            }
+        }
+        check::<3>();
+        check::<4>();
+        check::<5>();
+    }
+
+    #[test]
+    fn test_render() {
+        let offsets = neighbour_offsets::<3>();
+        let pocket: Pocket<3> = parse_input(INPUT.as_bytes());
+        assert_eq!(render(&pocket), "z=0\n.#.\n..#\n###\n");
+        // The first cycle of the example, trimmed to the active cubes
+        assert_eq!(
+            render(&generate(&pocket, &offsets, &Rule::default())),
+            "z=-1
+#..
+..#
+.#.
+
+z=0
+#.#
+.##
+.#.
+
+z=1
+#..
 ..#
-###"
-            .as_bytes(),
+.#.
+"
+        );
+
+        let offsets = neighbour_offsets::<4>();
+        let pocket: Pocket<4> = parse_input(INPUT.as_bytes());
+        let text = render(&generate(&pocket, &offsets, &Rule::default()));
+        assert!(text.starts_with("z=-1, w=-1\n#..\n..#\n.#.\n\nz=0, w=-1\n"));
+        assert_eq!(text.matches(", w=").count(), 9);
+        assert_eq!(render(&Pocket::<2>::new()), "");
+        assert_eq!(
+            render(&parse_input::<_, 2>(INPUT.as_bytes())),
+            INPUT.to_string() + "\n"
         );
-        let mut expected = vec![HashMap::new(), HashMap::new(), HashMap::new()];
-        expected[0].insert(0, CubeState::Inactive);
-        expected[0].insert(1, CubeState::Active);
-        expected[0].insert(2, CubeState::Inactive);
-        expected[1].insert(0, CubeState::Inactive);
-        expected[1].insert(1, CubeState::Inactive);
-        expected[1].insert(2, CubeState::Active);
-        expected[2].insert(0, CubeState::Active);
-        expected[2].insert(1, CubeState::Active);
-        expected[2].insert(2, CubeState::Active);
-        assert_eq!(input, HashMap::from_iter(expected.into_iter().enumerate()));
     }
 }
@@ -1,109 +1,573 @@
+use nom::branch::alt;
 use nom::bytes::complete::tag;
-use nom::character::complete::{alpha1, digit1, line_ending, space1};
-use nom::combinator::{all_consuming, opt, recognize};
-use nom::multi::{many1, separated_list0};
-use nom::sequence::{separated_pair, terminated, tuple};
+use nom::character::complete::{alpha1, digit1, space1};
+use nom::combinator::{all_consuming, map_res, recognize, value, verify};
+use nom::multi::separated_list1;
+use nom::sequence::{pair, preceded, separated_pair, tuple};
 use nom::IResult;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
+use std::fmt;
 use std::fs;
-use std::iter::FromIterator;
 
-type BaggageRule<'a> = (&'a str, Vec<(u8, &'a str)>);
+type BaggageRule<'a> = (&'a str, Vec<(u32, &'a str)>);
 type BaggageRules<'a> = Vec<BaggageRule<'a>>;
 
-fn bag_specifier(input: &str) -> IResult<&str, &str> {
-    terminated(
-        recognize(separated_pair(alpha1, space1, alpha1)), // two alphabetic words, separated by a space..
-        tuple((tag(" bag"), opt(tag("s")))),               // ..terminated by " bag(s)"
+// A line of input which isn't a valid rule
+#[derive(Debug, PartialEq)]
+struct ParseError<'a> {
+    line: usize, // 1-based
+    text: &'a str,
+}
+
+impl fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Couldn't parse line {}: '{}'", self.line, self.text)
+    }
+}
+
+// One word of a bag's colour. A colour can have any number of words, so stop at "bag(s)".
+fn colour_word(input: &str) -> IResult<&str, &str> {
+    verify(alpha1, |word: &str| word != "bag" && word != "bags")(input)
+}
+
+// A bag's colour and whether it was referred to in the plural
+fn bag_name(input: &str) -> IResult<&str, (&str, bool)> {
+    pair(
+        recognize(separated_list1(space1, colour_word)), // any number of words..
+        preceded(
+            space1,
+            alt((value(true, tag("bags")), value(false, tag("bag")))), // ..then " bag(s)"
+        ),
     )(input)
 }
 
+fn bag_specifier(input: &str) -> IResult<&str, &str> {
+    bag_name(input).map(|(rem, (name, _))| (rem, name))
+}
+
+// A count and bag, where the count has to agree with "bag" or "bags"
+fn counted_bag(input: &str) -> IResult<&str, (u32, &str)> {
+    let (rem, (count, (name, _))) = verify(
+        separated_pair(map_res(digit1, str::parse::<u32>), space1, bag_name),
+        |(count, (_, plural))| (*count == 1) != *plural,
+    )(input)?;
+    Ok((rem, (count, name)))
+}
+
 fn baggage_rule(input: &str) -> IResult<&str, BaggageRule> {
     match tuple((
         bag_specifier,
         tag(" contain "),
-        separated_list0(tag(", "), separated_pair(digit1, space1, bag_specifier)),
-        opt(tag("no other bags")),
+        alt((
+            value(vec![], tag("no other bags")),
+            separated_list1(tag(", "), counted_bag),
+        )),
         tag("."),
     ))(input)
     {
         Ok((
             remaining,
             (
-                outer,    // The two words of the outer bag
+                outer,    // The words of the outer bag
                 _,        // " contain "
-                contents, // the vec of inner bags
-                _,        // maybe "no other bags"
+                contents, // the vec of inner bags, empty if "no other bags"
                 _,        // ending full stop
             ),
-        )) => Ok((
-            remaining,
-            (
-                outer,
-                contents
-                    .iter()
-                    .map(|(count, bag)| (str::parse::<u8>(*count).unwrap(), *bag))
-                    .collect(),
-            ),
-        )),
+        )) => Ok((remaining, (outer, contents))),
         Err(e) => Err(e),
     }
 }
 
-fn parse_baggage_rules(input: &str) -> IResult<&str, BaggageRules> {
-    all_consuming(many1(terminated(baggage_rule, line_ending)))(input)
+// Parse each line as a rule, collecting any lines which aren't valid rules rather than
+// giving up on the whole input. Blank lines are skipped.
+fn parse_baggage_rules(input: &str) -> (BaggageRules, Vec<ParseError>) {
+    let mut rules = vec![];
+    let mut errors = vec![];
+    for (i, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match all_consuming(baggage_rule)(line) {
+            Ok((_, rule)) => rules.push(rule),
+            Err(_) => errors.push(ParseError {
+                line: i + 1,
+                text: line,
+            }),
+        }
+    }
+    (rules, errors)
 }
 
-// Given a list of BaggageRules, return a map from bag type to allowed outer bags
-fn outer_bag_map<'a>(rules: &'a BaggageRules) -> HashMap<&'a str, Vec<&'a str>> {
-    rules
-        .iter()
-        .map(|(outer, inners)| inners.iter().map(move |inner| (inner, outer)))
-        .flatten()
-        .fold(HashMap::new(), |mut m, ((count, inner), outer)| {
-            if *count > 0 {
-                m.entry(inner).or_insert(vec![]).push(outer);
+type BagId = usize;
+
+// Which way to follow the edges of a BagGraph
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Contents,   // outer -> inner, as written in the rules
+    Containers, // inner -> outer, the reverse graph
+}
+
+#[derive(Debug, PartialEq)]
+enum GraphError<'a> {
+    UnknownBag(String),
+    // The bags forming the cycle, with the first bag repeated at the end
+    Cycle(Vec<&'a str>),
+    Overflow(&'a str),
+    // A command which doesn't make sense, with how to use it
+    Usage(String),
+}
+
+impl fmt::Display for GraphError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphError::UnknownBag(name) => write!(f, "Unknown bag '{}'", name),
+            GraphError::Cycle(bags) => {
+                write!(f, "Bag rules contain a cycle: {}", bags.join(" -> "))
+            }
+            GraphError::Overflow(name) => write!(f, "Bag count overflowed inside '{}'", name),
+            GraphError::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// The baggage rules as a graph. Bag names are interned to ids, which index into the
+// forward (outer -> inner) and reverse (inner -> outer) adjacency lists. Both lists
+// carry the count from the rule the edge came from.
+#[derive(Debug)]
+struct BagGraph<'a> {
+    names: Vec<&'a str>,
+    ids: HashMap<&'a str, BagId>,
+    contents: Vec<Vec<(u32, BagId)>>,
+    containers: Vec<Vec<(u32, BagId)>>,
+}
+
+impl<'a> From<&BaggageRules<'a>> for BagGraph<'a> {
+    fn from(rules: &BaggageRules<'a>) -> BagGraph<'a> {
+        let mut graph = BagGraph {
+            names: vec![],
+            ids: HashMap::new(),
+            contents: vec![],
+            containers: vec![],
+        };
+        for (outer, inners) in rules {
+            let outer = graph.intern(outer);
+            for (count, inner) in inners {
+                let inner = graph.intern(inner);
+                graph.contents[outer].push((*count, inner));
+                if *count > 0 {
+                    graph.containers[inner].push((*count, outer));
+                }
+            }
+        }
+        graph
+    }
+}
+
+impl<'a> BagGraph<'a> {
+    fn intern(&mut self, name: &'a str) -> BagId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+        let id = self.names.len();
+        self.names.push(name);
+        self.ids.insert(name, id);
+        self.contents.push(vec![]);
+        self.containers.push(vec![]);
+        id
+    }
+
+    fn id(&self, name: &str) -> Result<BagId, GraphError<'a>> {
+        self.ids
+            .get(name)
+            .copied()
+            .ok_or_else(|| GraphError::UnknownBag(name.to_string()))
+    }
+
+    // Turn a DFS stack which has just run into `repeated` again into the cycle it closes
+    fn cycle_on_stack(&self, stack: &[(BagId, usize)], repeated: BagId) -> Vec<&'a str> {
+        let start = stack.iter().position(|(id, _)| *id == repeated).unwrap();
+        stack[start..]
+            .iter()
+            .map(|(id, _)| self.names[*id])
+            .chain(std::iter::once(self.names[repeated]))
+            .collect()
+    }
+
+    // Return the first cycle found in the rules, if there is one
+    fn find_cycle(&self) -> Option<Vec<&'a str>> {
+        let mut done = vec![false; self.names.len()];
+        let mut on_stack = vec![false; self.names.len()];
+        for root in 0..self.names.len() {
+            if done[root] {
+                continue;
+            }
+            // Iterative DFS, so deeply nested rules can't overflow the stack. Each entry
+            // is a bag and the index of the next of its contents to visit.
+            let mut stack = vec![(root, 0)];
+            on_stack[root] = true;
+            while let Some(&(id, next)) = stack.last() {
+                if let Some(&(_, inner)) = self.contents[id].get(next) {
+                    stack.last_mut().unwrap().1 += 1;
+                    if on_stack[inner] {
+                        return Some(self.cycle_on_stack(&stack, inner));
+                    }
+                    if !done[inner] {
+                        on_stack[inner] = true;
+                        stack.push((inner, 0));
+                    }
+                } else {
+                    done[id] = true;
+                    on_stack[id] = false;
+                    stack.pop();
+                }
+            }
+        }
+        None
+    }
+
+    fn edges(&self, id: BagId, direction: Direction) -> &[(u32, BagId)] {
+        match direction {
+            Direction::Contents => &self.contents[id],
+            Direction::Containers => &self.containers[id],
+        }
+    }
+
+    // Flags for every bag reachable from the given bag by following edges in the given
+    // direction. The bag itself is only included if it's part of a cycle.
+    fn reachable(&self, root: BagId, direction: Direction) -> Vec<bool> {
+        let mut seen = vec![false; self.names.len()];
+        let mut queue: VecDeque<BagId> = self
+            .edges(root, direction)
+            .iter()
+            .map(|(_, id)| *id)
+            .collect();
+        while let Some(id) = queue.pop_front() {
+            if !seen[id] {
+                seen[id] = true;
+                queue.extend(self.edges(id, direction).iter().map(|(_, id)| *id));
+            }
+        }
+        seen
+    }
+
+    // All bags which can eventually contain the given bag
+    fn all_outer(&self, name: &str) -> Result<HashSet<&'a str>, GraphError<'a>> {
+        Ok(self
+            .names
+            .iter()
+            .zip(self.reachable(self.id(name)?, Direction::Containers))
+            .filter(|(_, seen)| *seen)
+            .map(|(name, _)| *name)
+            .collect())
+    }
+
+    // Compute a value for the given bag from the values of the bags directly inside it,
+    // and their counts. Each bag's value is only computed once, and cycles are reported
+    // rather than recursing forever.
+    fn fold_inside<T, F>(&self, root: BagId, combine: F) -> Result<T, GraphError<'a>>
+    where
+        T: Copy,
+        F: Fn(BagId, &[(u32, T)]) -> Result<T, GraphError<'a>>,
+    {
+        let mut values: Vec<Option<T>> = vec![None; self.names.len()];
+        let mut on_stack = vec![false; self.names.len()];
+        let mut stack = vec![(root, 0)];
+        on_stack[root] = true;
+        while let Some(&(id, next)) = stack.last() {
+            if let Some(&(_, inner)) = self.contents[id].get(next) {
+                stack.last_mut().unwrap().1 += 1;
+                if on_stack[inner] {
+                    return Err(GraphError::Cycle(self.cycle_on_stack(&stack, inner)));
+                }
+                if values[inner].is_none() {
+                    on_stack[inner] = true;
+                    stack.push((inner, 0));
+                }
+            } else {
+                // Everything inside this bag has a value, so we can compute its own
+                let inners = self.contents[id]
+                    .iter()
+                    .map(|(count, inner)| (*count, values[*inner].unwrap()))
+                    .collect::<Vec<_>>();
+                values[id] = Some(combine(id, &inners)?);
+                on_stack[id] = false;
+                stack.pop();
+            }
+        }
+        Ok(values[root].unwrap())
+    }
+
+    // The total number of bags inside the given bag
+    fn total_contained(&self, name: &str) -> Result<u64, GraphError<'a>> {
+        self.fold_inside(self.id(name)?, |id, inners: &[(u32, u64)]| {
+            inners
+                .iter()
+                .try_fold(0u64, |sum, (count, total)| {
+                    total
+                        .checked_add(1)
+                        .and_then(|n| n.checked_mul(u64::from(*count)))
+                        .and_then(|n| n.checked_add(sum))
+                })
+                .ok_or(GraphError::Overflow(self.names[id]))
+        })
+    }
+
+    // How many levels of bags are nested inside the given bag
+    fn depth(&self, name: &str) -> Result<u32, GraphError<'a>> {
+        self.fold_inside(self.id(name)?, |_, inners: &[(u32, u32)]| {
+            Ok(inners.iter().map(|(_, depth)| depth + 1).max().unwrap_or(0))
+        })
+    }
+
+    // The shortest chain of bags leading from the outer bag down to the inner one, as
+    // each bag after the outer one along with how many of it the previous bag holds
+    fn path(
+        &self,
+        outer: &str,
+        inner: &str,
+    ) -> Result<Option<Vec<(u32, &'a str)>>, GraphError<'a>> {
+        let (outer, inner) = (self.id(outer)?, self.id(inner)?);
+        // BFS, remembering the edge by which each bag was first reached
+        let mut reached_by: Vec<Option<(u32, BagId)>> = vec![None; self.names.len()];
+        let mut queue = VecDeque::from(vec![outer]);
+        while let Some(id) = queue.pop_front() {
+            if id == inner {
+                let mut path = vec![];
+                let mut id = inner;
+                while id != outer {
+                    let (count, prev) = reached_by[id].unwrap();
+                    path.push((count, self.names[id]));
+                    id = prev;
+                }
+                path.reverse();
+                return Ok(Some(path));
+            }
+            for (count, next) in &self.contents[id] {
+                if *next != outer && reached_by[*next].is_none() {
+                    reached_by[*next] = Some((*count, id));
+                    queue.push_back(*next);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    // Bags which don't contain any other bags
+    fn leaves(&self) -> Vec<&'a str> {
+        self.names
+            .iter()
+            .zip(&self.contents)
+            .filter(|(_, contents)| contents.is_empty())
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    // Which bags to export: all of them, or only those reachable from the given bag in
+    // the given direction, along with the bag itself
+    fn selection(&self, around: Option<(&str, Direction)>) -> Result<Vec<bool>, GraphError<'a>> {
+        Ok(match around {
+            None => vec![true; self.names.len()],
+            Some((name, direction)) => {
+                let root = self.id(name)?;
+                let mut selected = self.reachable(root, direction);
+                selected[root] = true;
+                selected
             }
-            m
         })
+    }
+
+    // Render the selected bags as a Graphviz digraph, with edges labelled by count
+    fn to_dot(&self, selected: &[bool], direction: Direction) -> String {
+        let mut out = String::from("digraph bags {\n");
+        for (id, name) in self
+            .names
+            .iter()
+            .enumerate()
+            .filter(|(id, _)| selected[*id])
+        {
+            out += &format!("    {};\n", quote(name));
+            for (count, other) in self.edges(id, direction) {
+                if selected[*other] {
+                    out += &format!(
+                        "    {} -> {} [label=\"{}\"];\n",
+                        quote(name),
+                        quote(self.names[*other]),
+                        count
+                    );
+                }
+            }
+        }
+        out += "}\n";
+        out
+    }
+
+    // Render the selected bags as a JSON object of adjacency lists
+    fn to_json(&self, selected: &[bool], direction: Direction) -> String {
+        let lists = self
+            .names
+            .iter()
+            .enumerate()
+            .filter(|(id, _)| selected[*id])
+            .map(|(id, name)| {
+                let edges = self
+                    .edges(id, direction)
+                    .iter()
+                    .filter(|(_, other)| selected[*other])
+                    .map(|(count, other)| {
+                        format!(
+                            "{{\"count\": {}, \"bag\": {}}}",
+                            count,
+                            quote(self.names[*other])
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                format!("  {}: [{}]", quote(name), edges.join(", "))
+            })
+            .collect::<Vec<_>>();
+        format!("{{\n{}\n}}\n", lists.join(",\n"))
+    }
 }
 
-fn get_all_outer<'a>(target: &'a str, outer_map: &'a HashMap<&str, Vec<&str>>) -> HashSet<&'a str> {
-    let mut all_outers = HashSet::new();
-    if let Some(outers) = outer_map.get(target) {
-        all_outers.extend(outers);
-        for outer in outers {
-            all_outers.extend(get_all_outer(outer, outer_map));
+// Double-quote a bag name, for both DOT and JSON output
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// The options for exporting: which way to draw the edges, and the bag to draw the graph
+// around, if any. As with queries, a bag name is made from the words after --from or --to,
+// up to the next option, so it doesn't need quoting.
+type ExportOptions = (Direction, Option<(String, Direction)>);
+
+fn export_options<'a>(args: impl Iterator<Item = String>) -> Result<ExportOptions, GraphError<'a>> {
+    let mut args = args.peekable();
+    let mut direction = Direction::Contents;
+    let mut around = None;
+    while let Some(arg) = args.next() {
+        let mut words = vec![];
+        while let Some(word) = args.next_if(|word| !word.starts_with("--")) {
+            words.push(word);
         }
+        let bag = || {
+            if words.is_empty() {
+                Err(GraphError::Usage(format!("{} needs a bag name", arg)))
+            } else {
+                Ok(words.join(" "))
+            }
+        };
+        match arg.as_str() {
+            "--reverse" if words.is_empty() => direction = Direction::Containers,
+            "--from" => around = Some((bag()?, Direction::Contents)),
+            "--to" => around = Some((bag()?, Direction::Containers)),
+            "--reverse" => {
+                return Err(GraphError::Usage(format!(
+                    "Unexpected '{}'",
+                    words.join(" ")
+                )))
+            }
+            _ => return Err(GraphError::Usage(format!("Unknown option '{}'", arg))),
+        }
+    }
+    Ok((direction, around))
+}
+
+// Export the rules: <dot|json> [--reverse] [--from <bag> | --to <bag>]
+fn export<'a>(
+    graph: &BagGraph<'a>,
+    format: &str,
+    args: impl Iterator<Item = String>,
+) -> Result<(), GraphError<'a>> {
+    let (direction, around) = export_options(args)?;
+    let selected = graph.selection(around.as_ref().map(|(bag, d)| (bag.as_str(), *d)))?;
+    match format {
+        "dot" => print!("{}", graph.to_dot(&selected, direction)),
+        "json" => print!("{}", graph.to_json(&selected, direction)),
+        _ => unreachable!(),
     }
-    all_outers
+    Ok(())
 }
 
-fn get_total_contained<'a>(specifier: &str, rules: &'a HashMap<&str, Vec<(u8, &str)>>) -> u32 {
-    rules
-        .get(specifier)
-        .unwrap()
-        .iter()
-        .map(|(count, specifier)| *count as u32 * (1 + get_total_contained(specifier, rules)))
-        .sum()
+// The two bags for the path command, written as "<outer bag> to <inner bag>"
+fn path_bags<'a>(args: &[String]) -> Result<(String, String), GraphError<'a>> {
+    match args.iter().position(|arg| arg == "to") {
+        Some(to) if to > 0 && to + 1 < args.len() => {
+            Ok((args[..to].join(" "), args[to + 1..].join(" ")))
+        }
+        _ => Err(GraphError::Usage(
+            "Usage: path <outer bag> to <inner bag>".to_string(),
+        )),
+    }
+}
+
+// Answer a question about the rules. Bag names are made from the rest of the arguments, so
+// they don't need quoting.
+fn query<'a>(graph: &BagGraph<'a>, command: &str, args: Vec<String>) -> Result<(), GraphError<'a>> {
+    let bag = args.join(" ");
+    match command {
+        "contains" => {
+            let mut outers = graph.all_outer(&bag)?.into_iter().collect::<Vec<_>>();
+            outers.sort_unstable();
+            println!("{} bags can contain {}:", outers.len(), bag);
+            for outer in outers {
+                println!("  {}", outer);
+            }
+        }
+        "inside" => println!("{} contains {} total.", bag, graph.total_contained(&bag)?),
+        "depth" => println!("{} has {} levels of bags inside.", bag, graph.depth(&bag)?),
+        "path" => {
+            let (outer, inner) = path_bags(&args)?;
+            match graph.path(&outer, &inner)? {
+                Some(path) => println!(
+                    "{}{}",
+                    outer,
+                    path.iter()
+                        .map(|(count, bag)| format!(" -> {} {}", count, bag))
+                        .collect::<String>()
+                ),
+                None => println!("{} can't contain {}", outer, inner),
+            }
+        }
+        "leaves" if !args.is_empty() => {
+            return Err(GraphError::Usage("Usage: leaves".to_string()));
+        }
+        "leaves" => {
+            for leaf in graph.leaves() {
+                println!("{}", leaf);
+            }
+        }
+        _ => return Err(GraphError::Usage(format!("Unknown command '{}'", command))),
+    }
+    Ok(())
 }
 
 fn main() {
-    let fname = env::args()
-        .skip(1)
-        .next()
-        .expect("Please provide path to input file!");
+    let mut args = env::args().skip(1);
+    let fname = args.next().expect("Please provide path to input file!");
     let input = fs::read_to_string(fname).unwrap();
-    let rules = parse_baggage_rules(&input).unwrap().1;
-    let map = outer_bag_map(&rules);
-    let outers = get_all_outer("shiny gold", &map);
-    println!("{} possible outers", outers.len());
+    let (rules, errors) = parse_baggage_rules(&input);
+    for error in errors {
+        eprintln!("{}", error);
+    }
+    let graph = BagGraph::from(&rules);
 
-    println!(
-        "Shiny gold contains {} total.",
-        get_total_contained("shiny gold", &HashMap::from_iter(rules))
-    );
+    let result = match args.next() {
+        Some(format) if format == "dot" || format == "json" => export(&graph, &format, args),
+        Some(command) => query(&graph, &command, args.collect()),
+        None => {
+            if let Some(cycle) = graph.find_cycle() {
+                eprintln!("{}", GraphError::Cycle(cycle));
+            }
+            graph
+                .all_outer("shiny gold")
+                .map(|outers| println!("{} possible outers", outers.len()))
+                .and_then(|_| graph.total_contained("shiny gold"))
+                .map(|total| println!("Shiny gold contains {} total.", total))
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
 
 #[cfg(test)]
@@ -142,12 +606,58 @@ dotted black bags contain no other bags.
         assert_eq!(
             baggage_rule("ignominious spruce bags contain no other bags."),
             Ok(("", ("ignominious spruce", vec!()))),
-        )
+        );
+        assert_eq!(
+            baggage_rule("very pale blue bags contain 1 red bag, 70000 dull dark grey bags."),
+            Ok((
+                "",
+                (
+                    "very pale blue",
+                    vec!((1, "red"), (70000, "dull dark grey"))
+                )
+            )),
+        );
+        // Counts have to agree with "bag" or "bags"
+        assert!(baggage_rule("lol blue bags contain 2 ayy lmao bag.").is_err());
+        assert!(baggage_rule("lol blue bags contain 1 ayy lmao bags.").is_err());
+    }
+
+    #[test]
+    fn test_parse_baggage_rules() {
+        let (rules, errors) = parse_baggage_rules(
+            "light red bags contain 1 bright white bag.
+bright white bags contain 2 shiny gold bag.
+
+shiny gold bags contain no other bags.
+dark orange bags contain lots of bags.
+faded blue bags contain no other bags.",
+        );
+        assert_eq!(
+            rules,
+            vec!(
+                ("light red", vec!((1, "bright white"))),
+                ("shiny gold", vec!()),
+                ("faded blue", vec!()),
+            )
+        );
+        assert_eq!(
+            errors,
+            vec!(
+                ParseError {
+                    line: 2,
+                    text: "bright white bags contain 2 shiny gold bag."
+                },
+                ParseError {
+                    line: 5,
+                    text: "dark orange bags contain lots of bags."
+                },
+            )
+        );
     }
 
     #[test]
     fn test_outer_bag_map() {
-        let parsed = parse_baggage_rules(&input).unwrap().1;
+        let parsed = parse_baggage_rules(&input).0;
         let mut expected = HashMap::new();
         expected.insert("shiny gold", vec!["bright white", "muted yellow"]);
         expected.insert("bright white", vec!["light red", "dark orange"]);
@@ -159,28 +669,232 @@ dotted black bags contain no other bags.
         expected.insert("dark olive", vec!["shiny gold"]);
         expected.insert("vibrant plum", vec!["shiny gold"]);
         expected.insert("dotted black", vec!["dark olive", "vibrant plum"]);
-        assert_eq!(outer_bag_map(&parsed), expected);
+        let graph = BagGraph::from(&parsed);
+        let outer_map = graph
+            .containers
+            .iter()
+            .enumerate()
+            .filter(|(_, outers)| !outers.is_empty())
+            .map(|(id, outers)| {
+                (
+                    graph.names[id],
+                    outers
+                        .iter()
+                        .map(|(_, outer)| graph.names[*outer])
+                        .collect(),
+                )
+            })
+            .collect::<HashMap<_, Vec<_>>>();
+        assert_eq!(outer_map, expected);
     }
 
     #[test]
     fn test_get_all_outer() {
-        let parsed = parse_baggage_rules(&input).unwrap().1;
-        let map = outer_bag_map(&parsed);
+        let parsed = parse_baggage_rules(&input).0;
+        let graph = BagGraph::from(&parsed);
         assert_eq!(
-            get_all_outer("shiny gold", &map),
-            ["bright white", "muted yellow", "dark orange", "light red"]
+            graph.all_outer("shiny gold"),
+            Ok(["bright white", "muted yellow", "dark orange", "light red"]
                 .iter()
                 .cloned()
-                .collect(),
+                .collect()),
         );
+        assert_eq!(graph.all_outer("light red"), Ok(HashSet::new()));
     }
 
     #[test]
     fn test_get_total_contained() {
-        let parsed = parse_baggage_rules(&input).unwrap().1;
+        let parsed = parse_baggage_rules(&input).0;
+        let graph = BagGraph::from(&parsed);
+        assert_eq!(graph.total_contained("shiny gold"), Ok(32));
+        assert_eq!(graph.total_contained("faded blue"), Ok(0));
+        assert_eq!(
+            graph.total_contained("lol blue"),
+            Err(GraphError::UnknownBag("lol blue".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_bag_graph_cycle() {
+        let parsed = parse_baggage_rules(&input).0;
+        assert_eq!(BagGraph::from(&parsed).find_cycle(), None);
+
+        let parsed = parse_baggage_rules(
+            "light red bags contain 1 bright white bag.
+bright white bags contain 2 muted yellow bags, 1 faded blue bag.
+muted yellow bags contain 3 light red bags.
+faded blue bags contain no other bags.
+",
+        )
+        .0;
+        let graph = BagGraph::from(&parsed);
+        let cycle = vec!["light red", "bright white", "muted yellow", "light red"];
+        assert_eq!(graph.find_cycle(), Some(cycle.clone()));
+        assert_eq!(
+            graph.total_contained("light red"),
+            Err(GraphError::Cycle(cycle))
+        );
+        assert_eq!(graph.total_contained("faded blue"), Ok(0));
+    }
+
+    #[test]
+    fn test_bag_graph_overflow() {
+        // Each level multiplies the count by 255, so eight levels deep overflows a u64
+        let rules = "abcdefghij"
+            .chars()
+            .zip("bcdefghijk".chars())
+            .map(|(o, i)| format!("level {} bags contain 255 level {} bags.\n", o, i))
+            .collect::<String>();
+        let parsed = parse_baggage_rules(&rules).0;
+        let graph = BagGraph::from(&parsed);
+        assert_eq!(graph.total_contained("level j"), Ok(255));
+        assert!(matches!(
+            graph.total_contained("level a"),
+            Err(GraphError::Overflow(_))
+        ));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let parsed = parse_baggage_rules(&input).0;
+        let graph = BagGraph::from(&parsed);
+        let selected = graph
+            .selection(Some(("dark olive", Direction::Contents)))
+            .unwrap();
+        assert_eq!(
+            graph.to_dot(&selected, Direction::Contents),
+            r#"digraph bags {
+    "faded blue";
+    "dark olive";
+    "dark olive" -> "faded blue" [label="3"];
+    "dark olive" -> "dotted black" [label="4"];
+    "dotted black";
+}
+"#
+        );
+        let selected = graph
+            .selection(Some(("bright white", Direction::Containers)))
+            .unwrap();
+        assert_eq!(
+            graph.to_dot(&selected, Direction::Containers),
+            r#"digraph bags {
+    "light red";
+    "bright white";
+    "bright white" -> "light red" [label="1"];
+    "bright white" -> "dark orange" [label="3"];
+    "dark orange";
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let parsed = parse_baggage_rules(&input).0;
+        let graph = BagGraph::from(&parsed);
+        let selected = graph
+            .selection(Some(("vibrant plum", Direction::Contents)))
+            .unwrap();
+        assert_eq!(
+            graph.to_json(&selected, Direction::Contents),
+            r#"{
+  "faded blue": [],
+  "vibrant plum": [{"count": 5, "bag": "faded blue"}, {"count": 6, "bag": "dotted black"}],
+  "dotted black": []
+}
+"#
+        );
+        assert_eq!(
+            graph.selection(Some(("lol blue", Direction::Contents))),
+            Err(GraphError::UnknownBag("lol blue".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_export_options() {
+        let args = |s: &str| {
+            s.split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+                .into_iter()
+        };
+        assert_eq!(export_options(args("")), Ok((Direction::Contents, None)));
+        assert_eq!(
+            export_options(args("--to shiny gold --reverse")),
+            Ok((
+                Direction::Containers,
+                Some(("shiny gold".to_string(), Direction::Containers))
+            ))
+        );
+        assert_eq!(
+            export_options(args("--from dark olive")),
+            Ok((
+                Direction::Contents,
+                Some(("dark olive".to_string(), Direction::Contents))
+            ))
+        );
+        let usage = |message: &str| Err(GraphError::Usage(message.to_string()));
+        assert_eq!(
+            export_options(args("--from --reverse")),
+            usage("--from needs a bag name")
+        );
+        assert_eq!(
+            export_options(args("--reverse shiny gold")),
+            usage("Unexpected 'shiny gold'")
+        );
+        assert_eq!(
+            export_options(args("--sideways")),
+            usage("Unknown option '--sideways'")
+        );
+    }
+
+    #[test]
+    fn test_depth() {
+        let parsed = parse_baggage_rules(&input).0;
+        let graph = BagGraph::from(&parsed);
+        assert_eq!(graph.depth("shiny gold"), Ok(2));
+        assert_eq!(graph.depth("light red"), Ok(4));
+        assert_eq!(graph.depth("dotted black"), Ok(0));
+    }
+
+    #[test]
+    fn test_path() {
+        let parsed = parse_baggage_rules(&input).0;
+        let graph = BagGraph::from(&parsed);
+        assert_eq!(
+            graph.path("dark orange", "dotted black"),
+            Ok(Some(vec![
+                (3, "bright white"),
+                (1, "shiny gold"),
+                (1, "dark olive"),
+                (4, "dotted black")
+            ]))
+        );
+        assert_eq!(graph.path("shiny gold", "shiny gold"), Ok(Some(vec![])));
+        assert_eq!(graph.path("shiny gold", "light red"), Ok(None));
+        assert_eq!(
+            graph.path("shiny gold", "lol blue"),
+            Err(GraphError::UnknownBag("lol blue".to_string()))
+        );
+        let args = |s: &str| s.split(' ').map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            path_bags(&args("shiny gold to dark olive")),
+            Ok(("shiny gold".to_string(), "dark olive".to_string()))
+        );
+        let usage = Err(GraphError::Usage(
+            "Usage: path <outer bag> to <inner bag>".to_string(),
+        ));
+        assert_eq!(path_bags(&args("shiny gold dark olive")), usage);
+        assert_eq!(path_bags(&args("to dark olive")), usage);
+        assert_eq!(path_bags(&args("shiny gold to")), usage);
+    }
+
+    #[test]
+    fn test_leaves() {
+        let parsed = parse_baggage_rules(&input).0;
         assert_eq!(
-            get_total_contained("shiny gold", &HashMap::from_iter(parsed)),
-            32
+            BagGraph::from(&parsed).leaves(),
+            vec!["faded blue", "dotted black"]
         );
     }
 }
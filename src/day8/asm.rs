@@ -0,0 +1,304 @@
+use crate::vm::Op;
+use nom::branch::alt;
+use nom::bytes::complete::tag_no_case;
+use nom::character::complete::{
+    alpha1, alphanumeric1, char, digit1, hex_digit1, one_of, space0, space1,
+};
+use nom::combinator::{all_consuming, map, map_res, opt, recognize, rest};
+use nom::multi::many0;
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
+use nom::IResult;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+
+impl TryFrom<(&str, i64)> for Op {
+    type Error = String;
+    fn try_from(i: (&str, i64)) -> Result<Op, String> {
+        match i.0 {
+            "acc" => Ok(Op::Acc(i.1)),
+            "jmp" => Ok(Op::Jmp(i.1)),
+            "nop" => Ok(Op::Nop(i.1)),
+            #[cfg(feature = "extended-isa")]
+            "mul" => Ok(Op::Mul(i.1)),
+            #[cfg(feature = "extended-isa")]
+            "jz" => Ok(Op::Jz(i.1)),
+            #[cfg(feature = "extended-isa")]
+            "jnz" => Ok(Op::Jnz(i.1)),
+            #[cfg(feature = "extended-isa")]
+            "out" => Ok(Op::Out),
+            #[cfg(feature = "extended-isa")]
+            "hlt" => Ok(Op::Hlt),
+            s => Err(format!("Unknown opcode '{}'!", s)),
+        }
+    }
+}
+
+// The canonical form of an instruction, as accepted by the original console
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Acc(n) => write!(f, "acc {:+}", n),
+            Op::Jmp(n) => write!(f, "jmp {:+}", n),
+            Op::Nop(n) => write!(f, "nop {:+}", n),
+            #[cfg(feature = "extended-isa")]
+            Op::Mul(n) => write!(f, "mul {:+}", n),
+            #[cfg(feature = "extended-isa")]
+            Op::Jz(n) => write!(f, "jz {:+}", n),
+            #[cfg(feature = "extended-isa")]
+            Op::Jnz(n) => write!(f, "jnz {:+}", n),
+            #[cfg(feature = "extended-isa")]
+            Op::Out => write!(f, "out"),
+            #[cfg(feature = "extended-isa")]
+            Op::Hlt => write!(f, "hlt"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize, // 1-based
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand<'a> {
+    Immediate(i64),
+    Label(&'a str),
+}
+
+// A signed decimal or hex number. The sign is optional.
+fn immediate(input: &str) -> IResult<&str, i64> {
+    map(
+        pair(
+            opt(one_of("+-")),
+            alt((
+                map_res(preceded(tag_no_case("0x"), hex_digit1), |hex| {
+                    i64::from_str_radix(hex, 16)
+                }),
+                map_res(digit1, str::parse::<i64>),
+            )),
+        ),
+        |(sign, n)| if sign == Some('-') { -n } else { n },
+    )(input)
+}
+
+fn identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        alt((alpha1, recognize(char('_')))),
+        many0(alt((alphanumeric1, recognize(char('_'))))),
+    ))(input)
+}
+
+fn operand(input: &str) -> IResult<&str, Operand> {
+    alt((
+        map(immediate, Operand::Immediate),
+        map(identifier, Operand::Label),
+    ))(input)
+}
+
+// An opcode and its operand. Only instructions which don't use an operand can leave it
+// out, which is checked when assembling.
+fn op(input: &str) -> IResult<&str, (&str, Option<Operand>)> {
+    pair(alpha1, opt(preceded(space1, operand)))(input)
+}
+
+// Whether an opcode has no operand, so shouldn't be given one
+fn takes_no_operand(opcode: &str) -> bool {
+    cfg!(feature = "extended-isa") && (opcode == "out" || opcode == "hlt")
+}
+
+// A line of source: an optional label definition, an optional instruction and an
+// optional comment, starting with ';' or '#'
+type Line<'a> = (Option<&'a str>, Option<(&'a str, Option<Operand<'a>>)>);
+
+fn line(input: &str) -> IResult<&str, Line> {
+    all_consuming(delimited(
+        space0,
+        pair(
+            opt(terminated(identifier, pair(char(':'), space0))),
+            opt(op),
+        ),
+        tuple((space0, opt(preceded(one_of(";#"), rest)))),
+    ))(input)
+}
+
+pub fn assemble(source: &str) -> Result<Vec<Op>, AsmError> {
+    // First pass: parse each line, and note the address each label refers to
+    let mut labels = HashMap::new();
+    let mut ops = vec![];
+    for (i, text) in source.lines().enumerate() {
+        let error = |message: String| AsmError {
+            line: i + 1,
+            message,
+        };
+        let (_, (label, op)) =
+            line(text).map_err(|_| error(format!("Syntax error: '{}'", text)))?;
+        if let Some(label) = label {
+            if labels.insert(label, ops.len()).is_some() {
+                return Err(error(format!("Label '{}' is already defined", label)));
+            }
+        }
+        if let Some(op) = op {
+            ops.push((i + 1, op));
+        }
+    }
+
+    // Second pass: turn labels into offsets relative to the instruction using them
+    ops.into_iter()
+        .enumerate()
+        .map(|(pc, (line, (opcode, operand)))| {
+            let error = |message: String| AsmError { line, message };
+            // An unknown opcode is the problem, whatever its operand
+            Op::try_from((opcode, 0)).map_err(error)?;
+            let value = match operand {
+                None if takes_no_operand(opcode) => 0,
+                Some(_) if takes_no_operand(opcode) => {
+                    return Err(error(format!("{} doesn't take an operand", opcode)))
+                }
+                None => return Err(error(format!("{} needs an operand", opcode))),
+                Some(Operand::Immediate(n)) => n,
+                Some(Operand::Label(label)) => match labels.get(label) {
+                    Some(target) => *target as i64 - pc as i64,
+                    None => return Err(error(format!("Undefined label '{}'", label))),
+                },
+            };
+            let op: Op = (opcode, value).try_into().map_err(error)?;
+            if let Some(Operand::Label(_)) = operand {
+                if op.jump_target(pc).is_none() && op.flipped().is_none() {
+                    return Err(error(format!("{} can't take a label", opcode)));
+                }
+            }
+            Ok(op)
+        })
+        .collect()
+}
+
+// Print a program in canonical form, one instruction per line, with the address of each
+// instruction and the target of each jmp in a trailing comment
+pub fn disassemble(prog: &[Op]) -> String {
+    prog.iter()
+        .enumerate()
+        .map(|(pc, op)| match op.jump_target(pc) {
+            Some(target) => format!("{:<12}; {:04} -> {:04}\n", op.to_string(), pc, target),
+            None => format!("{:<12}; {:04}\n", op.to_string(), pc),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_op() {
+        assert_eq!(assemble("acc +69"), Ok(vec![Op::Acc(69)]));
+        assert_eq!(assemble("jmp -257"), Ok(vec![Op::Jmp(-257)]));
+        assert_eq!(assemble("nop -123"), Ok(vec![Op::Nop(-123)]));
+        assert_eq!(assemble("acc 0x1F"), Ok(vec![Op::Acc(31)]));
+        assert_eq!(assemble("acc -0x10"), Ok(vec![Op::Acc(-16)]));
+        assert_eq!(assemble("acc 12"), Ok(vec![Op::Acc(12)]));
+    }
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+# Add up to 3, then stop
+start:  acc +1
+        nop +0     ; does nothing
+loop:
+        acc 0x2
+        jmp end
+        jmp loop
+end:";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                Op::Acc(1),
+                Op::Nop(0),
+                Op::Acc(2),
+                Op::Jmp(2),
+                Op::Jmp(-2),
+            ])
+        );
+
+        let error = |line, message: &str| {
+            Err(AsmError {
+                line,
+                message: message.to_string(),
+            })
+        };
+        assert_eq!(
+            assemble("acc +1\ndiv +2"),
+            error(2, "Unknown opcode 'div'!")
+        );
+        assert_eq!(
+            assemble("jmp nowhere"),
+            error(1, "Undefined label 'nowhere'")
+        );
+        assert_eq!(
+            assemble("a:\na: nop +0"),
+            error(2, "Label 'a' is already defined")
+        );
+        assert_eq!(assemble("a: acc a"), error(1, "acc can't take a label"));
+        assert_eq!(assemble("acc + 1"), error(1, "Syntax error: 'acc + 1'"));
+        assert_eq!(assemble("nop"), error(1, "nop needs an operand"));
+        assert_eq!(assemble("foo bar"), error(1, "Unknown opcode 'foo'!"));
+        assert_eq!(assemble("foo"), error(1, "Unknown opcode 'foo'!"));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let prog = vec![Op::Nop(0), Op::Acc(-1), Op::Jmp(-2), Op::Acc(0x10)];
+        let text = disassemble(&prog);
+        assert_eq!(
+            text,
+            "nop +0      ; 0000
+acc -1      ; 0001
+jmp -2      ; 0002 -> 0000
+acc +16     ; 0003
+"
+        );
+        assert_eq!(assemble(&text), Ok(prog));
+    }
+
+    #[cfg(feature = "extended-isa")]
+    #[test]
+    fn test_extended_isa() {
+        let source = "
+        acc +3
+loop:   out
+        acc -1
+        jnz loop
+        mul 0x10
+        hlt";
+        let prog = vec![
+            Op::Acc(3),
+            Op::Out,
+            Op::Acc(-1),
+            Op::Jnz(-2),
+            Op::Mul(16),
+            Op::Hlt,
+        ];
+        assert_eq!(assemble(source), Ok(prog.clone()));
+        assert_eq!(
+            disassemble(&prog),
+            "acc +3      ; 0000
+out         ; 0001
+acc -1      ; 0002
+jnz -2      ; 0003 -> 0001
+mul +16     ; 0004
+hlt         ; 0005
+"
+        );
+        assert_eq!(assemble(&disassemble(&prog)), Ok(prog));
+        assert!(assemble("out +1").is_err());
+        assert!(assemble("a: mul a").is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use crate::vm::Op;
+use std::fmt;
+
+// The control flow graph is over the pcs 0..=len, where len means the program terminated.
+// Each instruction has one successor, or two for conditional jumps, or none for hlt.
+// Successors outside that range are out of bounds and lead nowhere.
+fn successors(prog: &[Op], pc: usize, op: &Op) -> Vec<usize> {
+    op.successors(pc)
+        .into_iter()
+        .filter(|next| *next >= 0 && *next <= prog.len() as i64)
+        .map(|next| next as usize)
+        .collect()
+}
+
+// Flags for every pc from which the program can run off the end or halt, including the pc
+// just past the last instruction. Found by walking the control flow graph backwards from
+// those.
+pub fn terminating(prog: &[Op]) -> Vec<bool> {
+    let mut preds = vec![vec![]; prog.len() + 1];
+    let mut to_visit = vec![prog.len()];
+    for (pc, op) in prog.iter().enumerate() {
+        if op.successors(pc).is_empty() {
+            to_visit.push(pc);
+        }
+        for next in successors(prog, pc, op) {
+            preds[next].push(pc);
+        }
+    }
+    let mut terminates = vec![false; prog.len() + 1];
+    while let Some(pc) = to_visit.pop() {
+        if !terminates[pc] {
+            terminates[pc] = true;
+            to_visit.extend(&preds[pc]);
+        }
+    }
+    terminates
+}
+
+// Whether running the given instruction at pc can lead to termination
+pub fn could_terminate(prog: &[Op], terminates: &[bool], pc: usize, op: &Op) -> bool {
+    op.successors(pc).is_empty()
+        || successors(prog, pc, op)
+            .into_iter()
+            .any(|next| terminates[next])
+}
+
+// The strongly connected components of the graph which contain a cycle, found with
+// Tarjan's algorithm. The recursion is done with an explicit stack, so long programs
+// can't overflow the real one.
+fn loops(prog: &[Op]) -> Vec<Vec<usize>> {
+    let succs = prog
+        .iter()
+        .enumerate()
+        .map(|(pc, op)| {
+            let mut succs = successors(prog, pc, op);
+            succs.retain(|next| *next < prog.len());
+            succs
+        })
+        .collect::<Vec<_>>();
+    let mut index = vec![None; prog.len()];
+    let mut lowlink = vec![0; prog.len()];
+    let mut on_stack = vec![false; prog.len()];
+    let mut stack = vec![];
+    let mut next_index = 0;
+    let mut loops = vec![];
+    for root in 0..prog.len() {
+        if index[root].is_some() {
+            continue;
+        }
+        // Each entry is a pc and the index of the next of its successors to visit
+        let mut calls = vec![(root, 0)];
+        while let Some(&(pc, i)) = calls.last() {
+            if i == 0 {
+                index[pc] = Some(next_index);
+                lowlink[pc] = next_index;
+                next_index += 1;
+                stack.push(pc);
+                on_stack[pc] = true;
+            }
+            if let Some(&next) = succs[pc].get(i) {
+                calls.last_mut().unwrap().1 += 1;
+                match index[next] {
+                    None => calls.push((next, 0)),
+                    Some(next_index) if on_stack[next] => lowlink[pc] = lowlink[pc].min(next_index),
+                    Some(_) => {}
+                }
+                continue;
+            }
+            calls.pop();
+            if let Some(&(caller, _)) = calls.last() {
+                lowlink[caller] = lowlink[caller].min(lowlink[pc]);
+            }
+            if index[pc] == Some(lowlink[pc]) {
+                let mut component = vec![];
+                loop {
+                    let member = stack.pop().unwrap();
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == pc {
+                        break;
+                    }
+                }
+                if component.len() > 1 || succs[pc].contains(&pc) {
+                    component.sort_unstable();
+                    loops.push(component);
+                }
+            }
+        }
+    }
+    loops.sort_unstable();
+    loops
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Analysis {
+    // Instructions which can never be executed
+    pub unreachable: Vec<usize>,
+    // The strongly connected components of the graph which contain a cycle
+    pub loops: Vec<Vec<usize>>,
+    // Jumps to somewhere other than an instruction or the end, with their targets
+    pub out_of_bounds: Vec<(usize, i64)>,
+    // Reachable jmps and nops which could lead to termination if flipped
+    pub fixes: Vec<usize>,
+}
+
+pub fn analyse(prog: &[Op]) -> Analysis {
+    let mut reachable = vec![false; prog.len() + 1];
+    let mut to_visit = vec![0];
+    while let Some(pc) = to_visit.pop() {
+        if !reachable[pc] {
+            reachable[pc] = true;
+            if let Some(op) = prog.get(pc) {
+                to_visit.extend(successors(prog, pc, op));
+            }
+        }
+    }
+
+    let terminates = terminating(prog);
+    Analysis {
+        unreachable: (0..prog.len()).filter(|pc| !reachable[*pc]).collect(),
+        loops: loops(prog),
+        out_of_bounds: prog
+            .iter()
+            .enumerate()
+            .flat_map(|(pc, op)| op.successors(pc).into_iter().map(move |next| (pc, next)))
+            .filter(|(_, next)| *next < 0 || *next > prog.len() as i64)
+            .collect(),
+        fixes: prog
+            .iter()
+            .enumerate()
+            .filter(|(pc, _)| reachable[*pc])
+            .filter_map(|(pc, op)| op.flipped().map(|flipped| (pc, flipped)))
+            .filter(|(pc, flipped)| could_terminate(prog, &terminates, *pc, flipped))
+            .map(|(pc, _)| pc)
+            .collect(),
+    }
+}
+
+fn list(pcs: &[usize]) -> String {
+    pcs.iter()
+        .map(|pc| pc.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Unreachable instructions: {}", list(&self.unreachable))?;
+        writeln!(f, "Loops: {}", self.loops.len())?;
+        for cycle in &self.loops {
+            writeln!(f, "  {}", list(cycle))?;
+        }
+        writeln!(f, "Jumps out of bounds: {}", self.out_of_bounds.len())?;
+        for (pc, target) in &self.out_of_bounds {
+            writeln!(f, "  {} -> {}", pc, target)?;
+        }
+        writeln!(f, "Flips which could terminate: {}", list(&self.fixes))
+    }
+}
+
+// Render the control flow graph in Graphviz format. Loops are red, unreachable code is
+// grey, and the flips which could fix the program are dashed edges.
+pub fn to_dot(prog: &[Op], analysis: &Analysis) -> String {
+    let mut out = String::from("digraph program {\n    end [shape=doublecircle];\n");
+    for (pc, op) in prog.iter().enumerate() {
+        let style = if analysis.unreachable.contains(&pc) {
+            ", color=grey, fontcolor=grey"
+        } else if analysis.loops.iter().any(|cycle| cycle.contains(&pc)) {
+            ", color=red"
+        } else {
+            ""
+        };
+        out += &format!(
+            "    n{} [shape=box, label=\"{}: {}\"{}];\n",
+            pc, pc, op, style
+        );
+        let node = |next: i64| {
+            if next < 0 || next > prog.len() as i64 {
+                format!("oob{}", pc)
+            } else if next == prog.len() as i64 {
+                "end".to_string()
+            } else {
+                format!("n{}", next)
+            }
+        };
+        for next in op.successors(pc) {
+            if next < 0 || next > prog.len() as i64 {
+                out += &format!(
+                    "    oob{} [label=\"{}\", color=red, fontcolor=red];\n",
+                    pc, next
+                );
+            }
+            out += &format!("    n{} -> {};\n", pc, node(next));
+        }
+        if analysis.fixes.contains(&pc) {
+            let flipped = op.flipped().unwrap();
+            for next in flipped.successors(pc) {
+                out += &format!(
+                    "    n{} -> {} [style=dashed, label=\"{}\"];\n",
+                    pc,
+                    node(next),
+                    flipped
+                );
+            }
+        }
+    }
+    out += "}\n";
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyse() {
+        let prog = vec![
+            Op::Nop(0),
+            Op::Acc(1),
+            Op::Jmp(4),
+            Op::Acc(3),
+            Op::Jmp(-3),
+            Op::Acc(-99),
+            Op::Acc(1),
+            Op::Jmp(-4),
+            Op::Acc(6),
+        ];
+        assert_eq!(
+            analyse(&prog),
+            Analysis {
+                unreachable: vec![5, 8],
+                loops: vec![vec![1, 2, 3, 4, 6, 7]],
+                out_of_bounds: vec![],
+                fixes: vec![7],
+            }
+        );
+
+        let prog = vec![Op::Jmp(2), Op::Jmp(0), Op::Nop(-10), Op::Jmp(3), Op::Acc(1)];
+        assert_eq!(
+            analyse(&prog),
+            Analysis {
+                unreachable: vec![1, 4],
+                loops: vec![vec![1]],
+                out_of_bounds: vec![(3, 6)],
+                fixes: vec![3],
+            }
+        );
+    }
+
+    #[test]
+    fn test_terminating() {
+        let prog = vec![Op::Jmp(2), Op::Jmp(-1), Op::Acc(1), Op::Jmp(-2)];
+        assert_eq!(terminating(&prog), vec![false, false, false, false, true]);
+        let prog = vec![Op::Nop(2), Op::Jmp(-1), Op::Acc(1)];
+        assert_eq!(terminating(&prog), vec![false, false, true, true]);
+    }
+
+    #[cfg(feature = "extended-isa")]
+    #[test]
+    fn test_analyse_extended_isa() {
+        let prog = vec![
+            Op::Acc(3),
+            Op::Acc(-1),
+            Op::Jnz(-1),
+            Op::Jz(3),
+            Op::Nop(1),
+            Op::Hlt,
+            Op::Jz(-10),
+        ];
+        assert_eq!(
+            analyse(&prog),
+            Analysis {
+                unreachable: vec![],
+                loops: vec![vec![1, 2]],
+                out_of_bounds: vec![(6, -4)],
+                fixes: vec![4],
+            }
+        );
+        assert_eq!(
+            terminating(&prog),
+            vec![true, true, true, true, true, true, true, true]
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use crate::asm::disassemble;
+use crate::vm::{ExitReason, Op, Vm};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "Commands:
+  step [n]        (s) execute the next n instructions, default 1
+  continue        (c) run until a breakpoint, watch or the program stops
+  break <pc>      (b) stop before executing the instruction at pc
+  delete <pc>     (d) remove a breakpoint
+  watch [value]   (w) stop when the accumulator changes, or becomes value
+  unwatch             stop watching the accumulator
+  history [n]     (h) show the last n executed instructions, default 10
+  print           (p) show the pc, accumulator and next instruction
+  list            (l) disassemble the instructions around the pc
+  reset               start the program again, keeping breakpoints
+  quit            (q) leave the debugger";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Watch {
+    Change,
+    Equals(i64),
+}
+
+pub struct Debugger<'p> {
+    prog: &'p [Op],
+    word_bits: u32,
+    vm: Vm<'p>,
+    breakpoints: BTreeSet<usize>,
+    watch: Option<Watch>,
+    // The pc, instruction and resulting accumulator of each instruction executed so far
+    history: Vec<(usize, Op, i64)>,
+}
+
+impl<'p> Debugger<'p> {
+    pub fn new(prog: &'p [Op], word_bits: u32) -> Self {
+        Self {
+            prog,
+            word_bits,
+            vm: Vm::new(prog).with_word_size(word_bits),
+            breakpoints: BTreeSet::new(),
+            watch: None,
+            history: vec![],
+        }
+    }
+
+    // Read commands until "quit" or the end of the input
+    pub fn run(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{} instructions loaded. Type 'help' for commands.",
+            self.prog.len()
+        )?;
+        self.print(&mut out)?;
+        for line in input.lines() {
+            if !self.command(line?.trim(), &mut out)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Execute a single command, returning false if the debugger should exit
+    fn command(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let arg = words.next().map(str::parse::<i64>);
+        match (command, arg) {
+            ("step", None) | ("s", None) => self.step(1, out)?,
+            ("step", Some(Ok(n))) | ("s", Some(Ok(n))) if n > 0 => self.step(n as usize, out)?,
+            ("continue", None) | ("c", None) => self.cont(out)?,
+            ("break", Some(Ok(pc))) | ("b", Some(Ok(pc))) if pc >= 0 => {
+                self.breakpoints.insert(pc as usize);
+                writeln!(out, "Breakpoint set at {}", pc)?;
+            }
+            ("delete", Some(Ok(pc))) | ("d", Some(Ok(pc))) if pc >= 0 => {
+                if self.breakpoints.remove(&(pc as usize)) {
+                    writeln!(out, "Breakpoint at {} removed", pc)?;
+                } else {
+                    writeln!(out, "No breakpoint at {}", pc)?;
+                }
+            }
+            ("watch", None) | ("w", None) => {
+                self.watch = Some(Watch::Change);
+                writeln!(out, "Watching for the accumulator to change")?;
+            }
+            ("watch", Some(Ok(n))) | ("w", Some(Ok(n))) => {
+                self.watch = Some(Watch::Equals(n));
+                writeln!(out, "Watching for the accumulator to become {}", n)?;
+            }
+            ("unwatch", None) => {
+                self.watch = None;
+                writeln!(out, "No longer watching the accumulator")?;
+            }
+            ("history", None) | ("h", None) => self.history(10, out)?,
+            ("history", Some(Ok(n))) | ("h", Some(Ok(n))) if n >= 0 => {
+                self.history(n as usize, out)?
+            }
+            ("print", None) | ("p", None) => self.print(out)?,
+            ("list", None) | ("l", None) => self.list(out)?,
+            ("reset", None) => {
+                self.vm = Vm::new(self.prog).with_word_size(self.word_bits);
+                self.history.clear();
+                self.print(out)?;
+            }
+            ("quit", None) | ("q", None) => return Ok(false),
+            ("help", _) => writeln!(out, "{}", HELP)?,
+            _ => writeln!(out, "Unknown command '{}'. Type 'help' for commands.", line)?,
+        }
+        Ok(true)
+    }
+
+    fn print(&self, out: &mut impl Write) -> io::Result<()> {
+        match self.vm.exit_reason() {
+            Some(reason) => writeln!(out, "{}", reason),
+            None => writeln!(
+                out,
+                "pc: {}, acc: {}, next: {}",
+                self.vm.pc(),
+                self.vm.acc(),
+                self.prog[self.vm.pc() as usize]
+            ),
+        }
+    }
+
+    fn list(&self, out: &mut impl Write) -> io::Result<()> {
+        let pc = self.vm.pc().max(0) as usize;
+        let (start, end) = (pc.saturating_sub(3), (pc + 4).min(self.prog.len()));
+        for (i, line) in disassemble(self.prog).lines().enumerate() {
+            if i >= start && i < end {
+                let marker = if i == pc { "=>" } else { "  " };
+                writeln!(out, "{} {}", marker, line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn history(&self, n: usize, out: &mut impl Write) -> io::Result<()> {
+        for (pc, op, acc) in &self.history[self.history.len().saturating_sub(n)..] {
+            writeln!(out, "{:>5}: {:<12} (acc: {})", pc, op.to_string(), acc)?;
+        }
+        Ok(())
+    }
+
+    // Execute one instruction, reporting it if the program has stopped instead
+    fn step_one(&mut self, out: &mut impl Write) -> io::Result<bool> {
+        let pc = self.vm.pc();
+        match self.vm.step() {
+            Ok(()) => {
+                let pc = pc as usize;
+                self.history.push((pc, self.prog[pc], self.vm.acc()));
+                Ok(true)
+            }
+            Err(reason) => {
+                writeln!(out, "{}", reason)?;
+                if let ExitReason::InfiniteLoop { pc, .. } = reason {
+                    // The last instruction executed is the one which took us back
+                    if let Some((closer, op, _)) = self.history.last() {
+                        writeln!(
+                            out,
+                            "The loop is closed by instruction {} ({}), which leads back to {}",
+                            closer, op, pc
+                        )?;
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    fn step(&mut self, n: usize, out: &mut impl Write) -> io::Result<()> {
+        for _ in 0..n {
+            if !self.step_one(out)? {
+                return Ok(());
+            }
+        }
+        self.print(out)
+    }
+
+    fn cont(&mut self, out: &mut impl Write) -> io::Result<()> {
+        // Always execute at least one instruction, so we can continue from a breakpoint
+        loop {
+            let acc = self.vm.acc();
+            if !self.step_one(out)? {
+                return Ok(());
+            }
+            let watched = match self.watch {
+                Some(Watch::Change) => self.vm.acc() != acc,
+                Some(Watch::Equals(n)) => self.vm.acc() == n && acc != n,
+                None => false,
+            };
+            if watched {
+                writeln!(out, "Accumulator changed from {} to {}", acc, self.vm.acc())?;
+                return self.print(out);
+            }
+            let pc = self.vm.pc();
+            if pc >= 0 && self.breakpoints.contains(&(pc as usize)) {
+                writeln!(out, "Breakpoint at {}", pc)?;
+                return self.print(out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(commands: &str) -> String {
+        let prog = vec![
+            Op::Nop(0),
+            Op::Acc(1),
+            Op::Jmp(4),
+            Op::Acc(3),
+            Op::Jmp(-3),
+            Op::Acc(-99),
+            Op::Acc(1),
+            Op::Jmp(-4),
+            Op::Acc(6),
+        ];
+        let mut out = vec![];
+        Debugger::new(&prog, 64)
+            .run(commands.as_bytes(), &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_step_and_break() {
+        let out = session("s 2\nb 7\nc\nh 2\nc\n");
+        assert_eq!(
+            out,
+            "9 instructions loaded. Type 'help' for commands.
+pc: 0, acc: 0, next: nop +0
+pc: 2, acc: 1, next: jmp +4
+Breakpoint set at 7
+Breakpoint at 7
+pc: 7, acc: 2, next: jmp -4
+    2: jmp +4       (acc: 1)
+    6: acc +1       (acc: 2)
+About to run instruction twice. Acc: 5, PC: 1
+The loop is closed by instruction 4 (jmp -3), which leads back to 1
+"
+        );
+    }
+
+    #[test]
+    fn test_watch() {
+        let out = session("watch\nc\nwatch 5\nc\nq\ns\n");
+        assert_eq!(
+            out,
+            "9 instructions loaded. Type 'help' for commands.
+pc: 0, acc: 0, next: nop +0
+Watching for the accumulator to change
+Accumulator changed from 0 to 1
+pc: 2, acc: 1, next: jmp +4
+Watching for the accumulator to become 5
+Accumulator changed from 2 to 5
+pc: 4, acc: 5, next: jmp -3
+"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, prelude::*};
+
+mod asm;
+mod cfg;
+mod debugger;
+mod repair;
+mod vm;
+use asm::{assemble, disassemble};
+use cfg::{analyse, to_dot};
+use debugger::Debugger;
+use repair::repair;
+use vm::Vm;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let fname = args
+        .next()
+        .expect("Please provide a path to the input file!");
+    let mut trace = false;
+    let mut disassembly = false;
+    let mut debug = false;
+    let mut analysis = false;
+    let mut dot = false;
+    let mut word_bits = 64;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trace" => trace = true,
+            "--disassemble" => disassembly = true,
+            "--debug" => debug = true,
+            "--analyse" => analysis = true,
+            "--cfg-dot" => dot = true,
+            "--word-size" => {
+                word_bits = args
+                    .next()
+                    .and_then(|bits| bits.parse().ok())
+                    .filter(|bits| (1..=64).contains(bits))
+                    .expect("Please provide a word size from 1 to 64 bits!")
+            }
+            _ => panic!("Unknown option '{}'", arg),
+        }
+    }
+    let mut input = String::new();
+    File::open(fname)
+        .unwrap()
+        .read_to_string(&mut input)
+        .expect("error reading file");
+    let prog = match assemble(&input) {
+        Ok(prog) => prog,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if disassembly {
+        print!("{}", disassemble(&prog));
+    }
+    if analysis {
+        print!("{}", analyse(&prog));
+    }
+    if dot {
+        print!("{}", to_dot(&prog, &analyse(&prog)));
+        return;
+    }
+    if debug {
+        let stdin = io::stdin();
+        Debugger::new(&prog, word_bits)
+            .run(stdin.lock(), io::stdout())
+            .expect("error talking to the terminal");
+        return;
+    }
+
+    // Let's run the program
+    let mut vm = Vm::new(&prog).with_word_size(word_bits);
+    if trace {
+        vm = vm.with_trace(|pc, op, acc| println!("{:>5}: {} (acc: {})", pc, op, acc));
+    }
+    println!("{}", vm.run());
+    #[cfg(feature = "extended-isa")]
+    {
+        if !vm.output().is_empty() {
+            println!("Output: {:?}", vm.output());
+        }
+    }
+    match repair(&prog, word_bits) {
+        Ok(fix) => println!(
+            "Fixed program by changing instruction {} from '{}' to '{}'. Acc: {}",
+            fix.index, fix.original, fix.replacement, fix.acc
+        ),
+        Err(e) => println!("{}", e),
+    }
+}
@@ -0,0 +1,151 @@
+use crate::cfg::{could_terminate, terminating};
+use crate::vm::{ExitReason, Op, Vm};
+
+// A single instruction flip which makes a program terminate
+#[derive(Debug, PartialEq)]
+pub struct Repair {
+    pub index: usize,
+    pub original: Op,
+    pub replacement: Op,
+    pub acc: i64,
+}
+
+// Find the jmp or nop which, when flipped, makes the program terminate. Only instructions
+// the broken program actually runs can be the problem, and only those which could jump
+// into code that terminates once they're flipped.
+pub fn repair(prog: &[Op], word_bits: u32) -> Result<Repair, String> {
+    let terminates = terminating(prog);
+    let candidate = |pc: usize| {
+        prog[pc]
+            .flipped()
+            .filter(|flipped| could_terminate(prog, &terminates, pc, flipped))
+    };
+    let (index, replacement, acc) = find_repair(prog, word_bits, &terminates, candidate)?;
+    Ok(Repair {
+        index,
+        original: prog[index],
+        replacement,
+        acc,
+    })
+}
+
+const ALREADY_TERMINATES: &str = "Program already terminates";
+const NO_FIX: &str = "No single jmp/nop flip fixes the program";
+
+// Without conditional jumps, every pc either always terminates or never does, and the
+// program goes the same way up to the first candidate whichever flip fixes it. None of the
+// code the flip leads to has run yet, or the program would already terminate, so the first
+// candidate is the fix. This runs up to it, flips it, and carries on to the end in one pass.
+#[cfg(not(feature = "extended-isa"))]
+fn find_repair(
+    prog: &[Op],
+    word_bits: u32,
+    terminates: &[bool],
+    candidate: impl Fn(usize) -> Option<Op>,
+) -> Result<(usize, Op, i64), String> {
+    if terminates[0] {
+        return Err(ALREADY_TERMINATES.to_string());
+    }
+    let mut vm = Vm::new(prog).with_word_size(word_bits);
+    let in_prog = |pc: i64| pc >= 0 && pc < prog.len() as i64;
+    if vm
+        .run_until(|vm| in_prog(vm.pc()) && candidate(vm.pc() as usize).is_some())
+        .is_some()
+    {
+        return Err(NO_FIX.to_string());
+    }
+    let index = vm.pc() as usize;
+    let replacement = candidate(index).ok_or_else(|| NO_FIX.to_string())?;
+    vm.step_as(replacement).map_err(|e| e.to_string())?;
+    stopped_cleanly(&vm.run())
+        .map(|acc| (index, replacement, acc))
+        .ok_or_else(|| NO_FIX.to_string())
+}
+
+// With conditional jumps, where the program goes after a flip depends on the accumulator,
+// so each candidate is tried in turn
+#[cfg(feature = "extended-isa")]
+fn find_repair(
+    prog: &[Op],
+    word_bits: u32,
+    _terminates: &[bool],
+    candidate: impl Fn(usize) -> Option<Op>,
+) -> Result<(usize, Op, i64), String> {
+    let mut executed = vec![];
+    let exit = Vm::new(prog)
+        .with_word_size(word_bits)
+        .with_trace(|pc, _, _| executed.push(pc))
+        .run();
+    if stopped_cleanly(&exit).is_some() {
+        return Err(ALREADY_TERMINATES.to_string());
+    }
+    executed
+        .into_iter()
+        .filter_map(|pc| candidate(pc).map(|flipped| (pc, flipped)))
+        .find_map(|(index, replacement)| {
+            let mut fixed = prog.to_vec();
+            fixed[index] = replacement;
+            let exit = Vm::new(&fixed).with_word_size(word_bits).run();
+            stopped_cleanly(&exit).map(|acc| (index, replacement, acc))
+        })
+        .ok_or_else(|| NO_FIX.to_string())
+}
+
+// The final accumulator, if the program ran off the end or halted
+fn stopped_cleanly(exit: &ExitReason) -> Option<i64> {
+    match exit {
+        ExitReason::Terminated { acc } => Some(*acc),
+        #[cfg(feature = "extended-isa")]
+        ExitReason::Halted { acc, .. } => Some(*acc),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repair() {
+        let prog = vec![
+            Op::Nop(0),
+            Op::Acc(1),
+            Op::Jmp(4),
+            Op::Acc(3),
+            Op::Jmp(-3),
+            Op::Acc(-99),
+            Op::Acc(1),
+            Op::Jmp(-4),
+            Op::Acc(6),
+        ];
+        assert_eq!(
+            repair(&prog, 64),
+            Ok(Repair {
+                index: 7,
+                original: Op::Jmp(-4),
+                replacement: Op::Nop(-4),
+                acc: 8,
+            })
+        );
+
+        let mut fixed = prog.clone();
+        fixed[7] = Op::Nop(-4);
+        assert_eq!(repair(&fixed, 64), Err(ALREADY_TERMINATES.to_string()));
+        assert_eq!(
+            repair(&[Op::Acc(1), Op::Jmp(-1), Op::Jmp(-2)], 64),
+            Err(NO_FIX.to_string())
+        );
+
+        // The accumulator keeps counting after the flip, with the word size
+        let prog = vec![Op::Acc(100), Op::Jmp(0), Op::Acc(100)];
+        assert_eq!(
+            repair(&prog, 8),
+            Ok(Repair {
+                index: 1,
+                original: Op::Jmp(0),
+                replacement: Op::Nop(0),
+                acc: -56,
+            })
+        );
+    }
+}
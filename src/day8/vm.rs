@@ -0,0 +1,407 @@
+#[cfg(feature = "extended-isa")]
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Op {
+    Acc(i64),
+    Jmp(i64),
+    Nop(i64),
+    // Multiply the accumulator
+    #[cfg(feature = "extended-isa")]
+    Mul(i64),
+    // Jump if the accumulator is zero
+    #[cfg(feature = "extended-isa")]
+    Jz(i64),
+    // Jump if the accumulator isn't zero
+    #[cfg(feature = "extended-isa")]
+    Jnz(i64),
+    // Emit the accumulator as output
+    #[cfg(feature = "extended-isa")]
+    Out,
+    // Stop the machine
+    #[cfg(feature = "extended-isa")]
+    Hlt,
+}
+
+impl Op {
+    // Where the pc goes after executing this instruction at the given pc, with the given
+    // accumulator
+    #[cfg_attr(not(feature = "extended-isa"), allow(unused_variables))]
+    pub fn next_pc(&self, pc: usize, acc: i64) -> i64 {
+        match self {
+            Op::Jmp(n) => (pc as i64).saturating_add(*n),
+            #[cfg(feature = "extended-isa")]
+            Op::Jz(n) if acc == 0 => (pc as i64).saturating_add(*n),
+            #[cfg(feature = "extended-isa")]
+            Op::Jnz(n) if acc != 0 => (pc as i64).saturating_add(*n),
+            #[cfg(feature = "extended-isa")]
+            Op::Hlt => pc as i64,
+            _ => pc as i64 + 1,
+        }
+    }
+
+    // The pc this instruction jumps to if it's a jump, whether or not it's taken
+    pub fn jump_target(&self, pc: usize) -> Option<i64> {
+        match self {
+            Op::Jmp(n) => Some((pc as i64).saturating_add(*n)),
+            #[cfg(feature = "extended-isa")]
+            Op::Jz(n) | Op::Jnz(n) => Some((pc as i64).saturating_add(*n)),
+            _ => None,
+        }
+    }
+
+    // Every pc which could follow this instruction, whatever the accumulator. Empty if
+    // the instruction stops the machine.
+    pub fn successors(&self, pc: usize) -> Vec<i64> {
+        match self {
+            Op::Jmp(_) => vec![self.jump_target(pc).unwrap()],
+            #[cfg(feature = "extended-isa")]
+            Op::Jz(_) | Op::Jnz(_) => vec![pc as i64 + 1, self.jump_target(pc).unwrap()],
+            #[cfg(feature = "extended-isa")]
+            Op::Hlt => vec![],
+            _ => vec![pc as i64 + 1],
+        }
+    }
+
+    // Whether where this instruction goes depends on the accumulator
+    #[cfg(feature = "extended-isa")]
+    pub fn is_conditional(&self) -> bool {
+        self.successors(0).len() > 1
+    }
+
+    // The instruction with jmp and nop swapped, if it's one of those
+    pub fn flipped(&self) -> Option<Op> {
+        match self {
+            Op::Jmp(n) => Some(Op::Nop(*n)),
+            Op::Nop(n) => Some(Op::Jmp(*n)),
+            _ => None,
+        }
+    }
+}
+
+// Why the machine stopped
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExitReason {
+    // The pc moved to just past the last instruction
+    Terminated {
+        acc: i64,
+    },
+    // The instruction at pc has already been executed once
+    InfiniteLoop {
+        pc: usize,
+        acc: i64,
+    },
+    // The pc moved somewhere other than an instruction or just past the last one
+    PcOutOfBounds {
+        pc: i64,
+    },
+    // A hlt instruction was executed
+    #[cfg(feature = "extended-isa")]
+    Halted {
+        pc: usize,
+        acc: i64,
+    },
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitReason::Terminated { acc } => {
+                write!(f, "Program terminated correctly. Acc: {}", acc)
+            }
+            ExitReason::InfiniteLoop { pc, acc } => write!(
+                f,
+                "About to run instruction twice. Acc: {}, PC: {}",
+                acc, pc
+            ),
+            ExitReason::PcOutOfBounds { pc } => write!(f, "PC out of bounds: {}", pc),
+            #[cfg(feature = "extended-isa")]
+            ExitReason::Halted { pc, acc } => write!(f, "Halted. Acc: {}, PC: {}", acc, pc),
+        }
+    }
+}
+
+type TraceHook<'p> = Box<dyn FnMut(usize, &Op, i64) + 'p>;
+
+// The handheld console. The accumulator is a signed integer of `word_bits` bits, which
+// wraps around on overflow.
+pub struct Vm<'p> {
+    prog: &'p [Op],
+    pc: i64,
+    acc: i64,
+    word_bits: u32,
+    executed: Vec<bool>,
+    trace: Option<TraceHook<'p>>,
+    // With conditional jumps, running an instruction twice doesn't mean we're stuck in a
+    // loop unless the accumulator is the same too. Only tracked if the program has any.
+    #[cfg(feature = "extended-isa")]
+    states: Option<HashSet<(usize, i64)>>,
+    #[cfg(feature = "extended-isa")]
+    halted: bool,
+    #[cfg(feature = "extended-isa")]
+    output: Vec<i64>,
+}
+
+impl<'p> Vm<'p> {
+    pub fn new(prog: &'p [Op]) -> Self {
+        Self {
+            prog,
+            pc: 0,
+            acc: 0,
+            word_bits: 64,
+            executed: vec![false; prog.len()],
+            trace: None,
+            #[cfg(feature = "extended-isa")]
+            states: if prog.iter().any(Op::is_conditional) {
+                Some(HashSet::new())
+            } else {
+                None
+            },
+            #[cfg(feature = "extended-isa")]
+            halted: false,
+            #[cfg(feature = "extended-isa")]
+            output: vec![],
+        }
+    }
+
+    pub fn with_word_size(mut self, bits: u32) -> Self {
+        assert!(bits > 0 && bits <= 64, "Word size must be 1 to 64 bits");
+        self.word_bits = bits;
+        self.acc = self.wrap(self.acc);
+        self
+    }
+
+    // Call the hook with the pc, instruction and resulting accumulator each time an
+    // instruction is executed
+    pub fn with_trace(mut self, hook: impl FnMut(usize, &Op, i64) + 'p) -> Self {
+        self.trace = Some(Box::new(hook));
+        self
+    }
+
+    pub fn pc(&self) -> i64 {
+        self.pc
+    }
+
+    pub fn acc(&self) -> i64 {
+        self.acc
+    }
+
+    // Every value emitted by out instructions so far
+    #[cfg(feature = "extended-isa")]
+    pub fn output(&self) -> &[i64] {
+        &self.output
+    }
+
+    // Whether the machine has already been in the state it's in now
+    fn looping(&self) -> bool {
+        let pc = self.pc as usize;
+        #[cfg(feature = "extended-isa")]
+        {
+            if let Some(states) = &self.states {
+                return states.contains(&(pc, self.acc));
+            }
+        }
+        self.executed[pc]
+    }
+
+    // Truncate a value to the word size, sign extending it back to an i64
+    fn wrap(&self, value: i64) -> i64 {
+        let shift = 64 - self.word_bits;
+        (value << shift) >> shift
+    }
+
+    // Why the machine would stop if it tried to execute the next instruction, if it would
+    pub fn exit_reason(&self) -> Option<ExitReason> {
+        #[cfg(feature = "extended-isa")]
+        {
+            if self.halted {
+                return Some(ExitReason::Halted {
+                    pc: self.pc as usize,
+                    acc: self.acc,
+                });
+            }
+        }
+        if self.pc == self.prog.len() as i64 {
+            Some(ExitReason::Terminated { acc: self.acc })
+        } else if self.pc < 0 || self.pc > self.prog.len() as i64 {
+            Some(ExitReason::PcOutOfBounds { pc: self.pc })
+        } else if self.looping() {
+            Some(ExitReason::InfiniteLoop {
+                pc: self.pc as usize,
+                acc: self.acc,
+            })
+        } else {
+            None
+        }
+    }
+
+    // Execute the next instruction, unless the machine has stopped
+    pub fn step(&mut self) -> Result<(), ExitReason> {
+        if let Some(reason) = self.exit_reason() {
+            return Err(reason);
+        }
+        self.execute(self.prog[self.pc as usize]);
+        Ok(())
+    }
+
+    // Execute op in place of the next instruction, as if the program had been patched,
+    // unless the machine has stopped
+    #[cfg(not(feature = "extended-isa"))]
+    pub fn step_as(&mut self, op: Op) -> Result<(), ExitReason> {
+        if let Some(reason) = self.exit_reason() {
+            return Err(reason);
+        }
+        self.execute(op);
+        Ok(())
+    }
+
+    fn execute(&mut self, op: Op) {
+        let pc = self.pc as usize;
+        self.executed[pc] = true;
+        #[cfg(feature = "extended-isa")]
+        {
+            if let Some(states) = self.states.as_mut() {
+                states.insert((pc, self.acc));
+            }
+        }
+        self.pc = op.next_pc(pc, self.acc);
+        match op {
+            Op::Acc(n) => self.acc = self.wrap(self.acc.wrapping_add(n)),
+            #[cfg(feature = "extended-isa")]
+            Op::Mul(n) => self.acc = self.wrap(self.acc.wrapping_mul(n)),
+            #[cfg(feature = "extended-isa")]
+            Op::Out => self.output.push(self.acc),
+            #[cfg(feature = "extended-isa")]
+            Op::Hlt => self.halted = true,
+            _ => {}
+        }
+        if let Some(hook) = self.trace.as_mut() {
+            hook(pc, &op, self.acc);
+        }
+    }
+
+    // Run until the predicate returns true before an instruction is executed, returning
+    // None, or until the machine stops
+    pub fn run_until(&mut self, mut pred: impl FnMut(&Self) -> bool) -> Option<ExitReason> {
+        loop {
+            if pred(self) {
+                return None;
+            }
+            if let Err(reason) = self.step() {
+                return Some(reason);
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> ExitReason {
+        self.run_until(|_| false).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let prog = vec![
+            Op::Nop(0),
+            Op::Acc(1),
+            Op::Jmp(4),
+            Op::Acc(3),
+            Op::Jmp(-3),
+            Op::Acc(-99),
+            Op::Acc(1),
+            Op::Jmp(-4),
+            Op::Acc(6),
+        ];
+        assert_eq!(
+            Vm::new(&prog).run(),
+            ExitReason::InfiniteLoop { pc: 1, acc: 5 }
+        );
+
+        let mut fixed = prog.clone();
+        fixed[7] = Op::Nop(-4);
+        assert_eq!(Vm::new(&fixed).run(), ExitReason::Terminated { acc: 8 });
+
+        assert_eq!(
+            Vm::new(&[Op::Jmp(-1)]).run(),
+            ExitReason::PcOutOfBounds { pc: -1 }
+        );
+        assert_eq!(
+            Vm::new(&[Op::Jmp(2)]).run(),
+            ExitReason::PcOutOfBounds { pc: 2 }
+        );
+    }
+
+    #[test]
+    fn test_step_and_run_until() {
+        let prog = vec![Op::Acc(2), Op::Acc(3), Op::Nop(0), Op::Acc(4)];
+        let mut vm = Vm::new(&prog);
+        assert_eq!(vm.step(), Ok(()));
+        assert_eq!((vm.pc(), vm.acc()), (1, 2));
+        assert_eq!(vm.run_until(|vm| vm.pc() == 3), None);
+        assert_eq!((vm.pc(), vm.acc()), (3, 5));
+        assert_eq!(vm.run(), ExitReason::Terminated { acc: 9 });
+        assert_eq!(vm.step(), Err(ExitReason::Terminated { acc: 9 }));
+    }
+
+    #[test]
+    fn test_word_size() {
+        let prog = vec![Op::Acc(100), Op::Acc(100)];
+        assert_eq!(
+            Vm::new(&prog).with_word_size(8).run(),
+            ExitReason::Terminated { acc: -56 }
+        );
+        assert_eq!(
+            Vm::new(&prog).with_word_size(16).run(),
+            ExitReason::Terminated { acc: 200 }
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let prog = vec![Op::Acc(1), Op::Jmp(2), Op::Acc(5), Op::Acc(-3)];
+        let mut trace = vec![];
+        Vm::new(&prog)
+            .with_trace(|pc, op, acc| trace.push((pc, *op, acc)))
+            .run();
+        assert_eq!(
+            trace,
+            vec![(0, Op::Acc(1), 1), (1, Op::Jmp(2), 1), (3, Op::Acc(-3), -2)]
+        );
+    }
+
+    #[cfg(feature = "extended-isa")]
+    #[test]
+    fn test_extended_isa() {
+        // Count down from 3, emitting each value, then compute 5 * 4
+        let prog = vec![
+            Op::Acc(3),
+            Op::Out,
+            Op::Acc(-1),
+            Op::Jnz(-2),
+            Op::Acc(5),
+            Op::Mul(4),
+            Op::Jz(2),
+            Op::Hlt,
+            Op::Acc(1),
+        ];
+        let mut vm = Vm::new(&prog);
+        assert_eq!(vm.run(), ExitReason::Halted { pc: 7, acc: 20 });
+        assert_eq!(vm.output(), &[3, 2, 1]);
+
+        // A loop is only infinite once the accumulator repeats too
+        let prog = vec![Op::Acc(1), Op::Jnz(0)];
+        assert_eq!(
+            Vm::new(&prog).run(),
+            ExitReason::InfiniteLoop { pc: 1, acc: 1 }
+        );
+        let prog = vec![Op::Acc(1), Op::Jz(2), Op::Jmp(-2), Op::Acc(1)];
+        assert_eq!(
+            Vm::new(&prog).with_word_size(2).run(),
+            ExitReason::Terminated { acc: 1 }
+        );
+    }
+}
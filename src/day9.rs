@@ -1,11 +1,196 @@
+use std::collections::{HashMap, VecDeque};
 use std::env;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+
+// A contiguous range of at least two numbers which sums to an invalid number
+#[derive(Debug, PartialEq)]
+struct Weakness {
+    start: usize,
+    end: usize, // inclusive
+    min: u64,
+    max: u64,
+}
+
+#[derive(Debug, PartialEq)]
+struct Analysis {
+    // The index and value of every number which isn't the sum of two of the preceding
+    // preamble numbers
+    invalid: Vec<(usize, u64)>,
+    // The weakness for the first invalid number, if there's an invalid number and a range
+    // summing to it
+    weakness: Option<Weakness>,
+}
+
+// The numbers in a sliding window, with how many times each occurs
+#[derive(Default)]
+struct Window(HashMap<u64, usize>);
+
+impl Window {
+    fn add(&mut self, n: u64) {
+        *self.0.entry(n).or_insert(0) += 1;
+    }
+
+    fn remove(&mut self, n: u64) {
+        if let Some(count) = self.0.get_mut(&n) {
+            *count -= 1;
+            if *count == 0 {
+                self.0.remove(&n);
+            }
+        }
+    }
+
+    // Whether two numbers at different positions in the window sum to n. Only needs one
+    // pass over the distinct numbers, rather than checking every pair.
+    fn has_pair_summing_to(&self, n: u64) -> bool {
+        self.0.iter().any(|(x, count)| {
+            *x <= n
+                && match self.0.get(&(n - x)) {
+                    Some(_) if n - x != *x => true,
+                    Some(_) => *count > 1,
+                    None => false,
+                }
+        })
+    }
+}
+
+// Checks numbers one at a time against the preamble before them, so only the preamble
+// needs to be kept rather than every number seen
+struct Validator {
+    preamble: usize,
+    recent: VecDeque<u64>,
+    window: Window,
+    index: usize,
+}
+
+impl Validator {
+    fn new(preamble: usize) -> Self {
+        Self {
+            preamble,
+            recent: VecDeque::with_capacity(preamble + 1),
+            window: Window::default(),
+            index: 0,
+        }
+    }
+
+    // Take the next number, returning it with its index if it's invalid
+    fn push(&mut self, n: u64) -> Option<(usize, u64)> {
+        let index = self.index;
+        self.index += 1;
+        let invalid = self.recent.len() == self.preamble && !self.window.has_pair_summing_to(n);
+        self.recent.push_back(n);
+        self.window.add(n);
+        if self.recent.len() > self.preamble {
+            if let Some(old) = self.recent.pop_front() {
+                self.window.remove(old);
+            }
+        }
+        if invalid {
+            Some((index, n))
+        } else {
+            None
+        }
+    }
+}
+
+fn find_invalid(input: &[u64], preamble: usize) -> Vec<(usize, u64)> {
+    let mut validator = Validator::new(preamble);
+    input.iter().filter_map(|n| validator.push(*n)).collect()
+}
+
+// Validate numbers as they're read, one per line, yielding each invalid number as soon
+// as it's seen. Blank lines are skipped, and lines which aren't numbers are errors.
+fn validate_stream(
+    input: impl BufRead,
+    preamble: usize,
+) -> impl Iterator<Item = io::Result<(usize, u64)>> {
+    let mut validator = Validator::new(preamble);
+    input.lines().filter_map(move |line| {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        match line.parse::<u64>() {
+            Ok(n) => validator.push(n).map(Ok),
+            Err(e) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' isn't a number: {}", line, e),
+            ))),
+        }
+    })
+}
+
+// Find a range of at least two numbers summing to the target. All the numbers are
+// unsigned, so the running sum of a range only grows as it extends to the right and
+// shrinks as it moves its start right, and two pointers find it in one pass.
+fn find_range(input: &[u64], target: u64) -> Option<(usize, usize)> {
+    let target = u128::from(target);
+    let mut start = 0;
+    let mut sum = 0u128;
+    for (end, n) in input.iter().enumerate() {
+        sum += u128::from(*n);
+        while sum > target && start < end {
+            sum -= u128::from(input[start]);
+            start += 1;
+        }
+        if sum == target && start < end {
+            return Some((start, end));
+        }
+    }
+    None
+}
+
+fn analyse(input: &[u64], preamble: usize) -> Analysis {
+    let invalid = find_invalid(input, preamble);
+    let weakness = invalid
+        .first()
+        .and_then(|(_, n)| find_range(input, *n))
+        .map(|(start, end)| {
+            let range = &input[start..=end];
+            Weakness {
+                start,
+                end,
+                min: *range.iter().min().unwrap(),
+                max: *range.iter().max().unwrap(),
+            }
+        });
+    Analysis { invalid, weakness }
+}
 
 fn main() {
-    let fname = env::args()
-        .skip(1)
+    let args: Vec<String> = env::args().skip(1).collect();
+    // With --stream, validate numbers as they're read, from the file or from stdin if
+    // it's "-". The weakness needs every number, so isn't looked for.
+    let stream = args.iter().any(|arg| arg == "--stream");
+    let mut args = args.iter().filter(|arg| *arg != "--stream");
+    let fname = args.next().expect("Please provide path to input file!");
+    let preamble = args
         .next()
-        .expect("Please provide path to input file!");
+        .map(|p| {
+            p.parse::<usize>()
+                .expect("Preamble length must be a number")
+        })
+        .unwrap_or(25);
+    if stream {
+        let input: Box<dyn BufRead> = match fname.as_str() {
+            "-" => Box::new(BufReader::new(io::stdin())),
+            fname => Box::new(BufReader::new(File::open(fname).unwrap())),
+        };
+        for result in validate_stream(input, preamble) {
+            match result {
+                Ok((i, n)) => println!("Not a sum of the preamble: {} (index {})", n, i),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
     let input = fs::read_to_string(fname)
         .unwrap()
         .lines()
@@ -13,30 +198,80 @@ fn main() {
         .collect::<Result<Vec<u64>, _>>()
         .unwrap();
 
-    let mut invalid = None;
-    for window in input.windows(26) {
-        if !window[0..25]
-            .iter()
-            .enumerate()
-            .flat_map(|(i, x)| window[i + 1..25].iter().map(move |y| x + y))
-            .any(|v| v == window[25])
-        {
-            println!("First non-sum of preamble: {}", window[25]);
-            invalid = Some(window[25]);
-        }
-    }
-
-    for i in 0..input.len() {
-        for j in (i + 1)..input.len() {
-            let slice = &input[i..=j];
-            if slice.iter().sum::<u64>() == invalid.unwrap() {
-                let minmax = slice.iter().fold((slice[0], slice[0]), |minmax, v| {
-                    (minmax.0.min(*v), minmax.1.max(*v))
-                });
-                println!("part2: {}", minmax.0 + minmax.1);
-                return;
-            }
+    let analysis = analyse(&input, preamble);
+    for (i, n) in &analysis.invalid {
+        println!("Not a sum of the preamble: {} (index {})", n, i);
+    }
+    match (analysis.invalid.first(), analysis.weakness) {
+        (None, _) => println!("Every number is valid"),
+        (Some((_, n)), None) => println!("First non-sum of preamble: {}, no range sums to it", n),
+        (Some((_, n)), Some(weakness)) => {
+            println!("First non-sum of preamble: {}", n);
+            println!("part2: {}", weakness.min + weakness.max);
         }
     }
-    println!("no value found :(");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: [u64; 20] = [
+        35, 20, 15, 25, 47, 40, 62, 55, 65, 95, 102, 117, 150, 182, 127, 219, 299, 277, 309, 576,
+    ];
+
+    #[test]
+    fn test_analyse() {
+        assert_eq!(
+            analyse(&INPUT, 5),
+            Analysis {
+                invalid: vec![(14, 127)],
+                weakness: Some(Weakness {
+                    start: 2,
+                    end: 5,
+                    min: 15,
+                    max: 47
+                }),
+            }
+        );
+        assert_eq!(find_invalid(&[1, 2, 3, 4, 8, 12], 2), vec![(3, 4), (4, 8)]);
+        assert_eq!(find_invalid(&[1, 2, 3, 4, 5, 6], 10), vec![]);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut window = Window::default();
+        [3, 5, 5].iter().for_each(|n| window.add(*n));
+        assert!(window.has_pair_summing_to(8));
+        assert!(window.has_pair_summing_to(10));
+        assert!(!window.has_pair_summing_to(6));
+        window.remove(5);
+        assert!(!window.has_pair_summing_to(10));
+    }
+
+    #[test]
+    fn test_find_range() {
+        assert_eq!(find_range(&INPUT, 127), Some((2, 5)));
+        assert_eq!(find_range(&[0, 5, 1], 5), Some((0, 1)));
+        assert_eq!(find_range(&[5, 1], 5), None);
+    }
+
+    #[test]
+    fn test_validate_stream() {
+        let input = INPUT.iter().map(|n| format!("{}\n", n)).collect::<String>();
+        let invalid = validate_stream(input.as_bytes(), 5)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(invalid, vec![(14, 127)]);
+
+        // Each invalid number comes out before the rest of the input is read
+        let mut results = validate_stream("1\n2\n\n4\n6\nnope\n".as_bytes(), 2);
+        assert_eq!(results.next().unwrap().unwrap(), (2, 4));
+        assert!(results.next().unwrap().is_err());
+        assert!(results.next().is_none());
+
+        let mut validator = Validator::new(0);
+        assert_eq!(validator.push(1), Some((0, 1)));
+        assert_eq!(validator.push(1), Some((1, 1)));
+    }
 }